@@ -1,12 +1,23 @@
+use std::fmt;
+
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+
+use self::binary::{write_value, Bytes};
+
+// Only used by the `binary-effects` backend, though it's always compiled and
+// tested.
+#[cfg_attr(not(feature = "binary-effects"), allow(dead_code))]
+mod binary;
 
-/// Wrapper for a serializeable value. We could later memoize this, change the
-/// serialized format to a string, etc. For now, and for a compact on-the-wire
-/// representation in JSON, we use a JSON value.
-#[derive(Serialize, Deserialize)]
+/// Wrapper for a serializeable value. By default, for a compact on-the-wire
+/// representation in JSON, we use a JSON value. With the `binary-effects`
+/// feature the value is encoded to bytes up front instead, which is much smaller
+/// to hold in memory for large games, and to save with a binary format.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(transparent)]
 pub(crate) struct EffectValue {
-  pub(crate) serialized: serde_json::Value,
+  pub(crate) serialized: <Active as Backend>::Repr,
 }
 
 /// Wrap and unwrap effect values.
@@ -16,18 +27,186 @@ impl EffectValue {
     T: Serialize + DeserializeOwned + 'static,
   {
     Ok(EffectValue {
-      serialized: serde_json::to_value(value)?,
+      serialized: Active::encode(value)?,
     })
   }
 
   pub fn get<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
-    serde_json::from_value(self.serialized.clone())
+    Active::decode(&self.serialized)
+  }
+}
+
+/// A representation for effect values.
+pub(crate) trait Backend {
+  type Repr: Clone + fmt::Debug + PartialEq + Serialize + DeserializeOwned;
+
+  fn encode<T: Serialize>(value: &T) -> serde_json::Result<Self::Repr>;
+
+  fn decode<T: DeserializeOwned>(repr: &Self::Repr) -> serde_json::Result<T>;
+}
+
+/// Effect values held as JSON values.
+#[cfg_attr(feature = "binary-effects", allow(dead_code))]
+pub(crate) struct Json;
+
+impl Backend for Json {
+  type Repr = serde_json::Value;
+
+  fn encode<T: Serialize>(value: &T) -> serde_json::Result<Self::Repr> {
+    serde_json::to_value(value)
+  }
+
+  fn decode<T: DeserializeOwned>(repr: &Self::Repr) -> serde_json::Result<T> {
+    T::deserialize(repr)
   }
 }
 
-///
-#[derive(Serialize)]
-pub(crate) struct EffectTree {
+/// Effect values held as bytes, in this crate's own binary encoding of the
+/// value's JSON data model. Values are converted to JSON values to be encoded,
+/// so this saves memory while the value is held, not work encoding it. The
+/// encoding describes itself, so the bytes can be decoded without knowing their
+/// type. That lets a tree be rendered, and saved as readable JSON exactly like
+/// the JSON backend does, while binary formats save the bytes themselves.
+#[cfg_attr(not(feature = "binary-effects"), allow(dead_code))]
+pub(crate) struct Binary;
+
+impl Backend for Binary {
+  type Repr = Bytes;
+
+  fn encode<T: Serialize>(value: &T) -> serde_json::Result<Self::Repr> {
+    let mut bytes = Vec::new();
+    write_value(&serde_json::to_value(value)?, &mut bytes);
+    Ok(Bytes(bytes))
+  }
+
+  fn decode<T: DeserializeOwned>(repr: &Self::Repr) -> serde_json::Result<T> {
+    T::deserialize(repr.to_value()?)
+  }
+}
+
+#[cfg(not(feature = "binary-effects"))]
+pub(crate) type Active = Json;
+#[cfg(feature = "binary-effects")]
+pub(crate) type Active = Binary;
+
+/// A memoized effect result along with the results of the effects it applied.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EffectTree {
+  /// A name for the effect, so serialized trees are readable.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) label: Option<String>,
   pub(crate) result: EffectValue,
   pub(crate) children: Vec<EffectTree>,
+  /// The interpreter's RNG state after the effect, if the effect drew from it.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) rng_seed: Option<u64>,
+  /// How long the effect took to run in microseconds, with the `profile` feature.
+  /// Times vary from run to run, so they aren't saved or compared, and replaying
+  /// an effect doesn't run it, so keeps whatever time it had.
+  #[serde(skip)]
+  pub(crate) micros: Option<u64>,
+}
+
+/// Trees are equal if they recorded the same effects, however long they took.
+impl PartialEq for EffectTree {
+  fn eq(&self, other: &Self) -> bool {
+    let EffectTree {
+      label,
+      result,
+      children,
+      rng_seed,
+      micros: _,
+    } = self;
+    *label == other.label
+      && *result == other.result
+      && *children == other.children
+      && *rng_seed == other.rng_seed
+  }
+}
+
+impl EffectTree {
+  /// How long the effect took to run in microseconds, including the effects it
+  /// applied, if it was timed.
+  pub fn micros(&self) -> Option<u64> {
+    self.micros
+  }
+
+  /// Walk the tree depth first, yielding each node along with its depth, where
+  /// this node is at depth zero.
+  pub fn walk(&self) -> impl Iterator<Item = (usize, &EffectTree)> {
+    let mut stack = vec![(0, self)];
+    std::iter::from_fn(move || {
+      let (depth, node) = stack.pop()?;
+      stack.extend(node.children.iter().rev().map(|child| (depth + 1, child)));
+      Some((depth, node))
+    })
+  }
+
+  /// Every node in the tree with the given label, in the order they were applied.
+  pub fn find_by_label(&self, label: &str) -> Vec<&EffectTree> {
+    self
+      .walk()
+      .map(|(_, node)| node)
+      .filter(|node| node.label.as_deref() == Some(label))
+      .collect()
+  }
+
+  /// The node reached by following `path`, a child index at each level, from
+  /// this node. An empty path is this node.
+  pub fn at_path(&self, path: &[usize]) -> Option<&EffectTree> {
+    path
+      .iter()
+      .try_fold(self, |node, &index| node.children.get(index))
+  }
+
+  /// The number of nodes in the tree, including this one.
+  pub fn node_count(&self) -> usize {
+    self.walk().count()
+  }
+
+  /// Render the tree for reading, one node per line with its label, if any, and
+  /// its result as JSON. Children are drawn beneath their parent:
+  ///
+  /// ```text
+  /// 69
+  /// ├─ {"Ok":"Played Mox Awesome"}
+  /// └─ next_u64: 16294208416658607535
+  /// ```
+  pub fn render_tree(&self) -> String {
+    let mut out = String::new();
+    self.render_node(&mut out, "", "");
+    out
+  }
+
+  fn render_node(&self, out: &mut String, branch: &str, indent: &str) {
+    let result = serde_json::to_string(&self.result).unwrap();
+    match &self.label {
+      Some(label) => out.push_str(&format!("{branch}{label}: {result}\n")),
+      None => out.push_str(&format!("{branch}{result}\n")),
+    }
+
+    for (index, child) in self.children.iter().enumerate() {
+      if index + 1 < self.children.len() {
+        child.render_node(out, &format!("{indent}├─ "), &format!("{indent}│  "));
+      } else {
+        child.render_node(out, &format!("{indent}└─ "), &format!("{indent}   "));
+      }
+    }
+  }
+
+  /// Flatten the tree into JSON lines, one line per node in the order they were
+  /// applied. Unlike the nested tree, the lines can be grepped and appended to.
+  pub fn to_jsonl(&self) -> String {
+    self
+      .walk()
+      .map(|(depth, node)| {
+        let line = json!({
+          "depth": depth,
+          "label": node.label,
+          "result": node.result,
+        });
+        format!("{line}\n")
+      })
+      .collect()
+  }
 }
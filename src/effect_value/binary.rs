@@ -0,0 +1,209 @@
+//! The binary encoding behind the `Binary` effect value backend. Values are
+//! written in the JSON data model, so the bytes describe themselves.
+
+use std::fmt;
+
+use serde::{
+  de::{self, Deserializer, SeqAccess, Visitor},
+  Deserialize, Serialize, Serializer,
+};
+use serde_json::{Map, Number, Value};
+
+/// An encoded value, see `Binary`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Bytes(pub(crate) Vec<u8>);
+
+impl Bytes {
+  pub(crate) fn to_value(&self) -> serde_json::Result<Value> {
+    let mut reader = Reader(&self.0);
+    let value = reader.value()?;
+    match reader.0 {
+      [] => Ok(value),
+      _ => Err(de::Error::custom("trailing bytes after encoded value")),
+    }
+  }
+}
+
+impl Serialize for Bytes {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+      self
+        .to_value()
+        .map_err(serde::ser::Error::custom)?
+        .serialize(serializer)
+    } else {
+      serializer.serialize_bytes(&self.0)
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+    if deserializer.is_human_readable() {
+      let mut bytes = Vec::new();
+      write_value(&Value::deserialize(deserializer)?, &mut bytes);
+      Ok(Bytes(bytes))
+    } else {
+      deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+  }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+  type Value = Bytes;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("an encoded effect value")
+  }
+
+  fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Bytes, E> {
+    Ok(Bytes(bytes.to_vec()))
+  }
+
+  fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Bytes, E> {
+    Ok(Bytes(bytes))
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Bytes, A::Error> {
+    let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+    while let Some(byte) = seq.next_element()? {
+      bytes.push(byte);
+    }
+    Ok(Bytes(bytes))
+  }
+}
+
+// Each value starts with one of these tags. Lengths and integers are written as
+// LEB128 varints, so the small numbers most effects return take a single byte.
+const NULL: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const UNSIGNED: u8 = 3;
+const NEGATIVE: u8 = 4;
+const FLOAT: u8 = 5;
+const STRING: u8 = 6;
+const ARRAY: u8 = 7;
+const OBJECT: u8 = 8;
+
+pub(crate) fn write_value(value: &Value, out: &mut Vec<u8>) {
+  match value {
+    Value::Null => out.push(NULL),
+    Value::Bool(false) => out.push(FALSE),
+    Value::Bool(true) => out.push(TRUE),
+    Value::Number(number) => {
+      if let Some(n) = number.as_u64() {
+        out.push(UNSIGNED);
+        write_varint(n, out);
+      } else if let Some(n) = number.as_i64() {
+        // Negative, so -(n + 1), i.e.: !n, fits in a u64.
+        out.push(NEGATIVE);
+        write_varint(!n as u64, out);
+      } else {
+        out.push(FLOAT);
+        out.extend(number.as_f64().unwrap().to_le_bytes());
+      }
+    }
+    Value::String(string) => {
+      out.push(STRING);
+      write_str(string, out);
+    }
+    Value::Array(items) => {
+      out.push(ARRAY);
+      write_varint(items.len() as u64, out);
+      for item in items {
+        write_value(item, out);
+      }
+    }
+    Value::Object(entries) => {
+      out.push(OBJECT);
+      write_varint(entries.len() as u64, out);
+      for (key, value) in entries {
+        write_str(key, out);
+        write_value(value, out);
+      }
+    }
+  }
+}
+
+fn write_str(string: &str, out: &mut Vec<u8>) {
+  write_varint(string.len() as u64, out);
+  out.extend(string.as_bytes());
+}
+
+fn write_varint(mut n: u64, out: &mut Vec<u8>) {
+  while n >= 0x80 {
+    out.push(n as u8 | 0x80);
+    n >>= 7;
+  }
+  out.push(n as u8);
+}
+
+/// Reads values written by `write_value` from the front of a slice.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+  fn value(&mut self) -> serde_json::Result<Value> {
+    Ok(match self.byte()? {
+      NULL => Value::Null,
+      FALSE => Value::Bool(false),
+      TRUE => Value::Bool(true),
+      UNSIGNED => Value::from(self.varint()?),
+      NEGATIVE => Value::from(!self.varint()? as i64),
+      FLOAT => {
+        let bytes = self.take(8)?.try_into().unwrap();
+        Number::from_f64(f64::from_le_bytes(bytes))
+          .map(Value::Number)
+          .ok_or_else(|| de::Error::custom("encoded float is not finite"))?
+      }
+      STRING => Value::String(self.string()?),
+      ARRAY => {
+        let len = self.varint()?;
+        let items = (0..len).map(|_| self.value());
+        Value::Array(items.collect::<serde_json::Result<_>>()?)
+      }
+      OBJECT => {
+        let len = self.varint()?;
+        let mut entries = Map::new();
+        for _ in 0..len {
+          let key = self.string()?;
+          entries.insert(key, self.value()?);
+        }
+        Value::Object(entries)
+      }
+      tag => return Err(de::Error::custom(format!("unknown value tag {tag}"))),
+    })
+  }
+
+  fn string(&mut self) -> serde_json::Result<String> {
+    let len = self.varint()?;
+    let bytes = self.take(usize::try_from(len).map_err(de::Error::custom)?)?;
+    String::from_utf8(bytes.to_vec()).map_err(de::Error::custom)
+  }
+
+  fn varint(&mut self) -> serde_json::Result<u64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+      let byte = self.byte()?;
+      n |= u64::from(byte & 0x7f) << shift;
+      if byte & 0x80 == 0 {
+        return Ok(n);
+      }
+    }
+    Err(de::Error::custom("encoded integer is too long"))
+  }
+
+  fn byte(&mut self) -> serde_json::Result<u8> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn take(&mut self, len: usize) -> serde_json::Result<&[u8]> {
+    if self.0.len() < len {
+      return Err(de::Error::custom("unexpected end of encoded value"));
+    }
+    let (taken, rest) = self.0.split_at(len);
+    self.0 = rest;
+    Ok(taken)
+  }
+}
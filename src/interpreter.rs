@@ -1,13 +1,23 @@
-use serde::{de::DeserializeOwned, Serialize};
+use std::{
+  collections::{HashMap, VecDeque},
+  fmt, mem,
+  ops::{Deref, DerefMut},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize, Serializer};
 
 use super::{
+  draw_card,
   effect_value::{EffectTree, EffectValue},
-  Game,
+  perform_state_based_actions, resolve_triggers, serialize_sorted, EffectError, Game, Phase,
+  TriggerRequest,
 };
 
 /// This simple interpreter acts a lot like an iterator over a tree. Every time
 /// we call "apply" it recurses into the effect tree and creates a child
-/// iterator to pass to the function.
+/// iterator to pass to the function. The child is only a view: it borrows the
+/// game, the RNG and the user interface from its parent, and writes its effects
+/// directly into the children of the node being applied.
 ///
 /// The child iterator tracks its position in the list of effects. If the
 /// effect's value is recorded in our list, then we return the value and skip
@@ -18,60 +28,969 @@ use super::{
 ///
 /// This memoizes a tree of function calls, which just like the rules for React
 /// hooks, must be a deterministic sequence (or tree!).
+///
+/// Randomness is part of the interpreter's interface: the RNG state is seeded
+/// up front and every draw is itself an effect, so replays see the same values.
+/// Likewise, choices are made by calling back into the user interface, and the
+/// answer is recorded so a replay never asks the same question twice.
+///
+/// Replaying an effect doesn't touch the game, so to rewind, the outermost
+/// interpreter keeps a checkpoint of the game before each effect it runs. That
+/// costs a copy of the game per effect, so it's only done when asked for with
+/// `with_rewind`.
+///
+/// The game is usually borrowed, but a forked or resumed interpreter owns its
+/// copy.
+///
+/// Nothing stops a replacement effect from replacing an event with the same
+/// event, so effects can only nest `max_depth` deep before the interpreter
+/// refuses to go any deeper, rather than overflowing the stack.
 #[derive(Serialize)]
 pub struct Interpreter<'a> {
-  pub(crate) game: &'a mut Game,
-  pub(crate) effects: Vec<EffectTree>,
+  pub(crate) game: MaybeOwned<'a, Game>,
+  pub(crate) effects: MaybeOwned<'a, Vec<EffectTree>>,
   pub(crate) position: usize,
+  pub(crate) rng_seed: MaybeOwned<'a, u64>,
+  /// Positions saved by name, to restore to later.
+  #[serde(serialize_with = "serialize_sorted")]
+  pub(crate) named_checkpoints: HashMap<String, usize>,
+  #[serde(skip)]
+  pub(crate) interface: MaybeOwned<'a, Box<dyn GameInterface>>,
+  #[serde(skip)]
+  pub(crate) checkpoints: Option<Vec<Option<Checkpoint>>>,
+  #[serde(skip)]
+  pub(crate) observer: Option<MaybeOwned<'a, Observer>>,
+  /// How deeply nested the effect being applied is, zero at the top level.
+  #[serde(skip)]
+  pub(crate) depth: usize,
+  #[serde(skip)]
+  pub(crate) max_depth: usize,
+  /// The number of cards the game should always have, see
+  /// `with_conservation_check`.
+  #[serde(skip)]
+  pub(crate) conserved_cards: Option<usize>,
+  /// Whether a malformed replacement effect is an error, rather than ignored,
+  /// see `with_strict_replacements`.
+  #[serde(skip)]
+  pub(crate) strict_replacements: bool,
+  /// Whether drawing from an empty library loses the game, rather than failing
+  /// the draw, see `with_lose_on_empty_draw`.
+  #[serde(skip)]
+  pub(crate) lose_on_empty_draw: bool,
+  /// How many times effects have really run, rather than being replayed, by
+  /// label, see `execution_count`.
+  #[serde(skip)]
+  pub(crate) executions: MaybeOwned<'a, HashMap<String, usize>>,
+  /// The choice the user interface wasn't ready to make, see `pending_choice`.
+  #[serde(skip)]
+  pub(crate) pending_choice: MaybeOwned<'a, Option<ChoiceRequest>>,
+}
+
+/// Read effects exported with `Interpreter::export_log`.
+pub fn import_log(json: &str) -> Result<Vec<EffectTree>, serde_json::Error> {
+  serde_json::from_str(json)
+}
+
+/// How deeply effects can nest unless `Interpreter::with_max_depth` says
+/// otherwise.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// An interpreter which owns its game, see `Interpreter::fork`.
+pub type OwnedInterpreter = Interpreter<'static>;
+
+/// How the interpreter interacts with the world outside the game: the user
+/// interface making choices, and the source of randomness. The interpreter
+/// records everything it's told, so an interface is only asked about effects
+/// which haven't been recorded yet.
+pub trait GameInterface {
+  /// Make a choice, returning the index of the chosen option.
+  fn choose(&mut self, request: &ChoiceRequest) -> usize;
+
+  /// Make a choice if the user interface is ready to, or return `None` to pause
+  /// the effect until it is, see `Interpreter::pending_choice`.
+  fn try_choose(&mut self, request: &ChoiceRequest) -> Option<usize> {
+    Some(self.choose(request))
+  }
+
+  /// Draw a random number, advancing the RNG state in `rng_seed`. The state is
+  /// kept by the interpreter, so that rewinding and saving the game rewind and
+  /// save the RNG too.
+  fn next_rng(&mut self, rng_seed: &mut u64) -> u64 {
+    let (state, value) = splitmix64(*rng_seed);
+    *rng_seed = state;
+    value
+  }
+}
+
+/// The interface an interpreter starts with, which can't make choices. Effects
+/// which need one stop, leaving the choice pending.
+pub struct DefaultInterface;
+
+impl GameInterface for DefaultInterface {
+  fn choose(&mut self, request: &ChoiceRequest) -> usize {
+    panic!("No choice callback to answer: {}", request.prompt)
+  }
+
+  fn try_choose(&mut self, _: &ChoiceRequest) -> Option<usize> {
+    None
+  }
+}
+
+/// An interface which makes a fixed list of choices in order, e.g.: for tests
+/// and replays. Randomness comes from the interpreter's seeded RNG as usual.
+pub struct ScriptedInterface {
+  choices: VecDeque<usize>,
+}
+
+impl ScriptedInterface {
+  pub fn new(choices: impl IntoIterator<Item = usize>) -> ScriptedInterface {
+    ScriptedInterface {
+      choices: choices.into_iter().collect(),
+    }
+  }
+}
+
+impl GameInterface for ScriptedInterface {
+  fn choose(&mut self, request: &ChoiceRequest) -> usize {
+    self
+      .choices
+      .pop_front()
+      .unwrap_or_else(|| panic!("No scripted choice to answer: {}", request.prompt))
+  }
+}
+
+/// An interface which gives the choices and random numbers recorded in an effect
+/// tree back in order, see `Interpreter::replay_subtree`.
+struct RecordedInterface {
+  choices: VecDeque<usize>,
+  numbers: VecDeque<u64>,
+}
+
+impl RecordedInterface {
+  fn new(tree: &EffectTree) -> RecordedInterface {
+    let recorded = |label: &'static str| {
+      tree
+        .walk()
+        .filter(move |(_, node)| node.label.as_deref() == Some(label))
+        .map(|(_, node)| &node.result)
+    };
+    RecordedInterface {
+      choices: recorded("choose").filter_map(|r| r.get().ok()).collect(),
+      numbers: recorded("next_u64").filter_map(|r| r.get().ok()).collect(),
+    }
+  }
+}
+
+impl GameInterface for RecordedInterface {
+  fn choose(&mut self, request: &ChoiceRequest) -> usize {
+    self
+      .choices
+      .pop_front()
+      .unwrap_or_else(|| panic!("No recorded choice to answer: {}", request.prompt))
+  }
+
+  fn next_rng(&mut self, _: &mut u64) -> u64 {
+    self
+      .numbers
+      .pop_front()
+      .expect("No recorded random number to draw")
+  }
+}
+
+/// An interface which makes choices by calling back into the user interface, see
+/// `Interpreter::with_choice_callback`.
+struct CallbackInterface<F>(F);
+
+impl<F: FnMut(&ChoiceRequest) -> usize> GameInterface for CallbackInterface<F> {
+  fn choose(&mut self, request: &ChoiceRequest) -> usize {
+    (self.0)(request)
+  }
+}
+
+/// An effect to apply as part of a batch, see `Interpreter::apply_all`.
+pub type BoxedEffect<T> = Box<dyn for<'x> FnOnce(&mut Interpreter<'x>) -> T>;
+
+/// Told about every effect applied, see `Interpreter::with_observer`.
+pub(crate) type Observer = Box<dyn FnMut(&ApplyEvent)>;
+
+/// Something the interpreter did while applying an effect. Observing these lets
+/// a caller follow the game as it runs, rather than reading the effect tree
+/// afterwards.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum ApplyEvent {
+  /// The effect ran, and returned `result`.
+  Executed {
+    label: Option<String>,
+    result: serde_json::Value,
+  },
+  /// The effect's result was already recorded, so it was returned without
+  /// running the effect.
+  Replayed {
+    label: Option<String>,
+    result: serde_json::Value,
+  },
+}
+
+/// The first place a re-executed game diverged from its recording, see
+/// `Interpreter::verify_replay`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayMismatch {
+  /// The indices of the divergent node and its ancestors, from the top level.
+  pub path: Vec<usize>,
+  /// The recorded result, or `None` if the recording has no node here.
+  pub expected: Option<serde_json::Value>,
+  /// The re-executed result, or `None` if the re-execution has no node here.
+  pub actual: Option<serde_json::Value>,
+}
+
+impl fmt::Display for ReplayMismatch {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let describe = |value: &Option<serde_json::Value>| match value {
+      Some(value) => value.to_string(),
+      None => "nothing".to_string(),
+    };
+    write!(
+      f,
+      "Replay diverged at {:?}: recorded {}, but got {}",
+      self.path,
+      describe(&self.expected),
+      describe(&self.actual)
+    )
+  }
+}
+
+/// Everything needed to carry on with a game later, e.g.: from a save file. The
+/// interpreter borrows its game, so can't be deserialized, but this owns it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SavedGame {
+  pub game: Game,
+  pub effects: Vec<EffectTree>,
+  pub position: usize,
+  pub rng_seed: u64,
+  /// Positions saved by name, see `Interpreter::checkpoint`.
+  #[serde(default, serialize_with = "serialize_sorted")]
+  pub named_checkpoints: HashMap<String, usize>,
+  /// The game before each effect, if the game could be rewound, so the resumed
+  /// game can rewind past the point it was saved at.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub(crate) checkpoints: Option<Vec<Option<Checkpoint>>>,
+}
+
+impl SavedGame {
+  /// An interpreter which carries on from where the game was saved, owning the
+  /// game. Callbacks aren't saved, so it needs its own to make choices or
+  /// observe it. Fails if the save refers to effects it doesn't have, e.g.:
+  /// because it was edited or corrupted.
+  pub fn resume(self) -> Result<OwnedInterpreter, String> {
+    let applied = self.effects.len();
+    if self.position > applied {
+      return Err(format!(
+        "Saved at effect {}, but only {applied} were applied",
+        self.position
+      ));
+    }
+    let checkpoints = self.checkpoints.as_ref().map_or(0, Vec::len);
+    if checkpoints > applied {
+      return Err(format!(
+        "Saved {checkpoints} checkpoints, but only {applied} effects were applied"
+      ));
+    }
+    if let Some(name) = self
+      .named_checkpoints
+      .iter()
+      .find_map(|(name, &position)| (position > applied).then_some(name))
+    {
+      return Err(format!("Checkpoint {name} is ahead of the effects applied"));
+    }
+
+    let mut interpreter = Interpreter::with_game(MaybeOwned::Owned(self.game), self.effects)
+      .with_rng_seed(self.rng_seed);
+    interpreter.position = self.position;
+    interpreter.named_checkpoints = self.named_checkpoints;
+    interpreter.checkpoints = self.checkpoints;
+    Ok(interpreter)
+  }
+}
+
+/// Interpreter state which is owned by the outermost interpreter, and borrowed
+/// by the views it creates while applying effects.
+pub(crate) enum MaybeOwned<'a, T> {
+  Owned(T),
+  Borrowed(&'a mut T),
+}
+
+impl<T> From<T> for MaybeOwned<'_, T> {
+  fn from(value: T) -> Self {
+    MaybeOwned::Owned(value)
+  }
+}
+
+impl<T> Deref for MaybeOwned<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    match self {
+      MaybeOwned::Owned(value) => value,
+      MaybeOwned::Borrowed(value) => value,
+    }
+  }
+}
+
+impl<T> DerefMut for MaybeOwned<'_, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    match self {
+      MaybeOwned::Owned(value) => value,
+      MaybeOwned::Borrowed(value) => value,
+    }
+  }
+}
+
+impl<T: Serialize> Serialize for MaybeOwned<'_, T> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    (**self).serialize(serializer)
+  }
+}
+
+/// The state needed to run the game forward again from just before an effect.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Checkpoint {
+  pub(crate) game: serde_json::Value,
+  pub(crate) rng_seed: u64,
+}
+
+/// A decision the user interface must make, answered with an index into
+/// `options`. `min` and `max` tell the front-end how many options to pick. The
+/// effects here only ask for one at a time, e.g.: discarding two cards is two
+/// requests, so the answer is a single index.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChoiceRequest {
+  pub prompt: String,
+  pub options: Vec<String>,
+  /// The fewest options which must be chosen.
+  pub min: usize,
+  /// The most options which may be chosen.
+  pub max: usize,
 }
 
 impl<'a> Interpreter<'a> {
-  pub(crate) fn apply<T, F>(&mut self, f: F) -> T
+  /// An interpreter for a new game, with no effects applied yet.
+  pub fn new(game: &'a mut Game) -> Interpreter<'a> {
+    Interpreter::from_effects(game, Vec::new())
+  }
+
+  /// The effects applied so far as JSON, to save and resume the game later with
+  /// `import_log` and `from_effects`. The game itself isn't part of the log.
+  pub fn export_log(&self) -> String {
+    serde_json::to_string(&*self.effects).unwrap()
+  }
+
+  /// An interpreter which replays previously recorded effects against `game`,
+  /// before running any new ones.
+  pub fn from_effects(game: &'a mut Game, effects: Vec<EffectTree>) -> Interpreter<'a> {
+    Interpreter::with_game(MaybeOwned::Borrowed(game), effects)
+  }
+
+  /// An interpreter for `game`, whether it's borrowed or owned.
+  fn with_game(game: MaybeOwned<'a, Game>, effects: Vec<EffectTree>) -> Interpreter<'a> {
+    Interpreter {
+      game,
+      effects: effects.into(),
+      position: 0,
+      rng_seed: 0.into(),
+      named_checkpoints: HashMap::new(),
+      interface: MaybeOwned::Owned(Box::new(DefaultInterface)),
+      checkpoints: None,
+      observer: None,
+      depth: 0,
+      max_depth: DEFAULT_MAX_DEPTH,
+      conserved_cards: None,
+      strict_replacements: false,
+      lose_on_empty_draw: false,
+      executions: HashMap::new().into(),
+      pending_choice: None.into(),
+    }
+  }
+
+  /// Seed the RNG. Replays must use the same seed as the original run.
+  pub fn with_rng_seed(mut self, rng_seed: u64) -> Self {
+    *self.rng_seed = rng_seed;
+    self
+  }
+
+  /// Set the interface which makes choices and draws random numbers.
+  pub fn with_interface(mut self, interface: impl GameInterface + 'static) -> Self {
+    *self.interface = Box::new(interface);
+    self
+  }
+
+  /// Set the callback which asks the user interface to make choices.
+  pub fn with_choice_callback(
+    self,
+    choice_callback: impl FnMut(&ChoiceRequest) -> usize + 'static,
+  ) -> Self {
+    self.with_interface(CallbackInterface(choice_callback))
+  }
+
+  /// Keep a checkpoint of the game before each effect, so that effects can be
+  /// undone with `rewind` and `restore`. Every checkpoint is a copy of the game,
+  /// which is saved along with it.
+  pub fn with_rewind(mut self) -> Self {
+    self.checkpoints.get_or_insert_with(Vec::new);
+    self
+  }
+
+  /// Limit how deeply effects can nest, see `apply` and `apply_try`.
+  pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+    self.max_depth = max_depth;
+    self
+  }
+
+  /// Fail with `EffectError::MalformedReplacement` when a replacement effect
+  /// registered for an event can't be deserialized. By default such effects are
+  /// ignored, which can hide bugs.
+  pub fn with_strict_replacements(mut self) -> Self {
+    self.strict_replacements = true;
+    self
+  }
+
+  /// Play by the real rule for drawing from an empty library: the draw doesn't
+  /// fail, instead the player loses the game the next time state-based actions
+  /// are checked. By default the draw fails with `EffectError::EmptyLibrary`.
+  pub fn with_lose_on_empty_draw(mut self) -> Self {
+    self.lose_on_empty_draw = true;
+    self
+  }
+
+  /// A debugging aid: panic as soon as a top level effect leaves the game with a
+  /// different number of cards than it has now, see `Game::assert_conservation`.
+  pub fn with_conservation_check(mut self) -> Self {
+    self.conserved_cards = Some(self.game.non_token_count());
+    self
+  }
+
+  /// Set a callback which is told about every effect applied, including the
+  /// nested ones, e.g.: to log them.
+  pub fn with_observer(mut self, observer: impl FnMut(&ApplyEvent) + 'static) -> Self {
+    self.observer = Some(MaybeOwned::Owned(Box::new(observer)));
+    self
+  }
+
+  /// Apply an effect. An effect is any function of the interpreter whose result
+  /// can be serialized, so that it can be recorded and replayed.
+  ///
+  /// # Panics
+  ///
+  /// Once effects are nested `max_depth` deep, there's no result to record in
+  /// place of the effect, so rather than overflowing the stack this panics with
+  /// `EffectError::DepthExceeded`, the effect's label and the depth, e.g.:
+  /// "Effects nested more than 256 deep, applying next_u64 at depth 256".
+  /// Effects which can fail should use `apply_try`, which records the error
+  /// instead.
+  pub fn apply<T, F>(&mut self, f: F) -> T
   where
     F: for<'x> FnOnce(&mut Interpreter<'x>) -> T,
     T: Serialize + DeserializeOwned + 'static,
     Self: Sized,
   {
-    if let Some(dec) = self.effects.get(self.position) {
-      self.position += 1;
-      let result: T = dec.result.get().unwrap();
+    self.apply_node(None, f)
+  }
+
+  /// Apply an effect, recording `label` alongside its result in the effect tree.
+  /// This panics like `apply` once effects are nested too deeply.
+  pub fn apply_labeled<T, F>(&mut self, label: &str, f: F) -> T
+  where
+    F: for<'x> FnOnce(&mut Interpreter<'x>) -> T,
+    T: Serialize + DeserializeOwned + 'static,
+    Self: Sized,
+  {
+    self.apply_node(Some(label.to_string()), f)
+  }
+
+  /// Apply a list of effects in order, as the children of a single effect. A
+  /// replay returns every result at once, without revisiting the children.
+  pub fn apply_all<T>(&mut self, effects: Vec<BoxedEffect<T>>) -> Vec<T>
+  where
+    T: Serialize + DeserializeOwned + 'static,
+    Self: Sized,
+  {
+    self.apply(move |int| effects.into_iter().map(|f| int.apply(f)).collect())
+  }
+
+  /// Apply a fallible effect. The whole `Result` is recorded in the effect tree,
+  /// so a replay fails in the same place, while the caller can use `?` to abort
+  /// as soon as a nested effect fails.
+  ///
+  /// Once effects are nested `max_depth` deep, the effect isn't run, and
+  /// `EffectError::DepthExceeded` is recorded in its place.
+  pub fn apply_try<T, E, F>(&mut self, f: F) -> Result<T, E>
+  where
+    F: for<'x> FnOnce(&mut Interpreter<'x>) -> Result<T, E>,
+    T: Serialize + DeserializeOwned + 'static,
+    E: From<EffectError> + Serialize + DeserializeOwned + 'static,
+    Self: Sized,
+  {
+    self.apply_guarded(None, f, |_, max_depth| {
+      Err(EffectError::DepthExceeded { max_depth }.into())
+    })
+  }
+
+  fn apply_node<T, F>(&mut self, label: Option<String>, f: F) -> T
+  where
+    F: for<'x> FnOnce(&mut Interpreter<'x>) -> T,
+    T: Serialize + DeserializeOwned + 'static,
+    Self: Sized,
+  {
+    let depth = self.depth;
+    self.apply_guarded(label, f, |label, max_depth| {
+      panic!(
+        "{}, applying {} at depth {depth}",
+        EffectError::DepthExceeded { max_depth },
+        label.unwrap_or("an unlabeled effect")
+      )
+    })
+  }
+
+  /// Apply an effect, or once effects are nested `max_depth` deep, record the
+  /// result of `too_deep` in its place. It's given the effect's label and the
+  /// maximum depth.
+  fn apply_guarded<T, F, D>(&mut self, label: Option<String>, f: F, too_deep: D) -> T
+  where
+    F: for<'x> FnOnce(&mut Interpreter<'x>) -> T,
+    D: FnOnce(Option<&str>, usize) -> T,
+    T: Serialize + DeserializeOwned + 'static,
+    Self: Sized,
+  {
+    if let Some(result) = self.replay_node() {
       return result;
     }
+
+    if self.depth >= self.max_depth {
+      let result = too_deep(label.as_deref(), self.max_depth);
+      return self.run_node(label, move |_| result);
+    }
+    self.run_node(label, f)
+  }
+
+  /// Return the recorded result of the next effect without running it, if it
+  /// has been recorded.
+  fn replay_node<T>(&mut self) -> Option<T>
+  where
+    T: Serialize + DeserializeOwned + 'static,
+  {
+    let dec = self.effects.get(self.position)?;
+    self.position += 1;
+    if let Some(rng_seed) = dec.rng_seed {
+      *self.rng_seed = rng_seed;
+    }
+    let result: T = dec.result.get().unwrap();
+    if let Some(observer) = &mut self.observer {
+      observer(&ApplyEvent::Replayed {
+        label: dec.label.clone(),
+        result: serde_json::to_value(&result).unwrap(),
+      });
+    }
+    Some(result)
+  }
+
+  /// Run an effect which hasn't been recorded yet, and record its result.
+  fn run_node<T, F>(&mut self, label: Option<String>, f: F) -> T
+  where
+    F: for<'x> FnOnce(&mut Interpreter<'x>) -> T,
+    T: Serialize + DeserializeOwned + 'static,
+  {
+    if let Some(checkpoints) = &mut self.checkpoints {
+      // Replayed effects never saw the game before them, so have no checkpoint.
+      checkpoints.resize_with(self.position, || None);
+      checkpoints.push(Some(Checkpoint {
+        game: serde_json::to_value(&*self.game).unwrap(),
+        rng_seed: *self.rng_seed,
+      }));
+    }
     self.position += 1;
+    if let Some(label) = &label {
+      self.count_execution(label);
+    }
+    if matches!(self.effects, MaybeOwned::Owned(_)) {
+      *self.pending_choice = None;
+    }
+
+    // Push the node first, and let the effect write its own effects straight into
+    // the node's children through a view which reborrows everything else from us.
+    self.effects.push(EffectTree {
+      label,
+      result: EffectValue::new(&()).unwrap(),
+      children: Vec::new(),
+      rng_seed: None,
+      micros: None,
+    });
+    let rng_seed = *self.rng_seed;
+    let outermost = matches!(self.effects, MaybeOwned::Owned(_));
+    let node = self.effects.last_mut().unwrap();
 
-    // This is annoying - we need a SimpleInterpreter<'x> - with the EXACT lifetime
-    // 'x but lifetime rules mean any we construct in this function have a
-    // lifetime 'y < 'x
-
-    // So we safe our own state, then restore it afterward. Like I said, silly! I'm
-    // sure there's a way to type this, but I think the fact that our "f" is a
-    // "impl FnOnce(&mut Self)", where Self is _our own type_, i.e.: with <'x>, is
-    // the problem.
-    //
-    // Is there a way to write a trait such that a method can take an argument that
-    // is a function, where the function's argument is a subtype by lifetime?
-    // Probably. Not bothering now.
-    let mut sub_int = Interpreter {
-      game: self.game,
-      effects: Vec::new(),
+    let mut view = Interpreter {
+      game: MaybeOwned::Borrowed(&mut *self.game),
+      effects: MaybeOwned::Borrowed(&mut node.children),
       position: 0,
+      rng_seed: MaybeOwned::Borrowed(&mut *self.rng_seed),
+      named_checkpoints: HashMap::new(),
+      interface: MaybeOwned::Borrowed(&mut *self.interface),
+      checkpoints: None,
+      observer: self.observer.as_deref_mut().map(MaybeOwned::Borrowed),
+      depth: self.depth + 1,
+      max_depth: self.max_depth,
+      conserved_cards: None,
+      strict_replacements: self.strict_replacements,
+      lose_on_empty_draw: self.lose_on_empty_draw,
+      executions: MaybeOwned::Borrowed(&mut *self.executions),
+      pending_choice: MaybeOwned::Borrowed(&mut *self.pending_choice),
     };
 
-    let outcome = f(&mut sub_int);
+    #[cfg(feature = "profile")]
+    let started = std::time::Instant::now();
+    let outcome = f(&mut view);
+    #[cfg(feature = "profile")]
+    {
+      node.micros = Some(started.elapsed().as_micros() as u64);
+    }
 
-    self.effects.push(EffectTree {
-      result: EffectValue::new(&outcome).unwrap(),
-      children: sub_int.effects,
-    });
+    // State-based actions and triggers wait for the top level effect to finish,
+    // and happen as part of it so that replaying the effect doesn't repeat them.
+    if outermost {
+      view.check_state_based_actions();
+      while !view.game.triggers.is_empty() {
+        resolve_triggers(&mut view);
+        view.check_state_based_actions();
+      }
+
+      if let Some(total) = self.conserved_cards {
+        if let Err(err) = view.game.assert_conservation(total) {
+          panic!("{err}");
+        }
+      }
+    }
+
+    node.result = EffectValue::new(&outcome).unwrap();
+    // Only record the RNG state for effects which consumed randomness, so that
+    // replaying the effect leaves the RNG where the original run did.
+    node.rng_seed = (*self.rng_seed != rng_seed).then_some(*self.rng_seed);
+
+    if let Some(observer) = &mut self.observer {
+      observer(&ApplyEvent::Executed {
+        label: node.label.clone(),
+        result: serde_json::to_value(&outcome).unwrap(),
+      });
+    }
 
     outcome
   }
 
-  pub(crate) fn game(&self) -> &Game {
-    self.game
+  /// Move on to the next phase or step of the turn, recorded like any other
+  /// effect. The active player draws a card in their draw step, and after the
+  /// end step the next player's turn starts. If the draw fails, the game stays
+  /// in the upkeep, so the game and the recorded failure agree.
+  pub fn advance_phase(&mut self) -> Result<Phase, EffectError> {
+    self.apply_labeled("advance_phase", |int| {
+      let phase = int.game().phase.next();
+      if phase == Phase::Draw {
+        int.apply_try(draw_card)?;
+      }
+
+      let game = int.game_mut();
+      game.phase = phase;
+      if phase == Phase::Untap {
+        game.active_player = (game.active_player + 1) % game.players.len();
+        game.start_turn();
+      }
+
+      Ok(phase)
+    })
+  }
+
+  /// Check state-based actions, e.g.: a player on zero life loses the game. They
+  /// are checked after every top level effect, and each action taken is
+  /// recorded in the effect tree.
+  pub fn check_state_based_actions(&mut self) {
+    perform_state_based_actions(self);
+  }
+
+  /// Queue a triggered ability, to resolve once the top level effect is done.
+  pub fn queue_trigger(&mut self, trigger: TriggerRequest) {
+    self.game.triggers.push(trigger);
+  }
+
+  /// Fork the interpreter, copying the game and the effects applied so far, to
+  /// explore a different line of play without disturbing this one. Callbacks
+  /// can't be copied, so the fork needs its own to make choices or observe it.
+  pub fn fork(&self) -> OwnedInterpreter {
+    Interpreter {
+      game: MaybeOwned::Owned(self.game.clone()),
+      effects: MaybeOwned::Owned(self.effects.clone()),
+      position: self.position,
+      rng_seed: MaybeOwned::Owned(*self.rng_seed),
+      named_checkpoints: self.named_checkpoints.clone(),
+      interface: MaybeOwned::Owned(Box::new(DefaultInterface)),
+      checkpoints: self.checkpoints.clone(),
+      observer: None,
+      depth: self.depth,
+      max_depth: self.max_depth,
+      conserved_cards: self.conserved_cards,
+      strict_replacements: self.strict_replacements,
+      lose_on_empty_draw: self.lose_on_empty_draw,
+      executions: MaybeOwned::Owned(self.executions.clone()),
+      pending_choice: MaybeOwned::Owned(self.pending_choice.clone()),
+    }
+  }
+
+  /// Save the game and the effects applied so far, to resume later.
+  pub fn save(&self) -> SavedGame {
+    SavedGame {
+      game: self.game.clone(),
+      effects: self.effects.clone(),
+      position: self.position,
+      rng_seed: *self.rng_seed,
+      named_checkpoints: self.named_checkpoints.clone(),
+      checkpoints: self.checkpoints.clone(),
+    }
+  }
+
+  /// The number of nodes across all of the recorded effect trees, to keep an eye
+  /// on how large the memoized game has grown.
+  pub fn total_nodes(&self) -> usize {
+    self.effects.iter().map(EffectTree::node_count).sum()
+  }
+
+  /// Take the recorded effects, e.g.: to replay them with a new interpreter.
+  pub fn into_effects(self) -> Vec<EffectTree> {
+    match self.effects {
+      MaybeOwned::Owned(effects) => effects,
+      MaybeOwned::Borrowed(effects) => mem::take(effects),
+    }
+  }
+
+  /// Rewind the last `steps` effects, restoring the game to how it was before
+  /// them. Effects after the rewind point are discarded, and the next call to
+  /// apply runs a new effect in their place.
+  ///
+  /// The interpreter must have been made `with_rewind`, and only effects it ran
+  /// itself can be rewound, not ones it replayed from a previously recorded tree.
+  pub fn rewind(&mut self, steps: usize) -> Result<(), String> {
+    let keep = self.effects.len().checked_sub(steps).ok_or_else(|| {
+      format!(
+        "Cannot rewind {steps} effects, only {} were applied",
+        self.effects.len()
+      )
+    })?;
+    let checkpoints = self
+      .checkpoints
+      .as_mut()
+      .ok_or_else(|| "Cannot rewind without with_rewind".to_string())?;
+    let checkpoint = checkpoints
+      .get(keep)
+      .and_then(Option::as_ref)
+      .ok_or_else(|| format!("No checkpoint to rewind to before effect {keep}"))?;
+
+    // Only discard the checkpoint once it's restored, so a failure keeps it.
+    *self.game = Game::deserialize(&checkpoint.game).map_err(|err| err.to_string())?;
+    *self.rng_seed = checkpoint.rng_seed;
+    checkpoints.truncate(keep);
+    self.effects.truncate(keep);
+    self.position = keep;
+
+    Ok(())
+  }
+
+  /// Check that a recorded game is deterministic, by running `program` again
+  /// from scratch against a copy of `game` and comparing every result with the
+  /// recording. The effects themselves can't be recorded, so `program` must be
+  /// the one which made the recording. The RNG is seeded the same way, and the
+  /// recorded choices are given again, so the only differences come from
+  /// nondeterminism in the effects, e.g.: randomness not drawn from the
+  /// interpreter, or HashMap iteration order.
+  pub fn verify_replay<F>(
+    game: &Game,
+    effects: &[EffectTree],
+    rng_seed: u64,
+    program: F,
+  ) -> Result<(), ReplayMismatch>
+  where
+    F: for<'x> FnOnce(&mut Interpreter<'x>),
+  {
+    let choices = effects
+      .iter()
+      .flat_map(EffectTree::walk)
+      .filter(|(_, node)| node.label.as_deref() == Some("choose"))
+      .filter_map(|(_, node)| node.result.get::<usize>().ok());
+
+    let mut game = game.clone();
+    let mut interpreter = Interpreter::new(&mut game)
+      .with_rng_seed(rng_seed)
+      .with_interface(ScriptedInterface::new(choices.collect::<Vec<_>>()));
+    program(&mut interpreter);
+
+    first_mismatch(effects, &interpreter.into_effects(), &mut Vec::new()).map_or(Ok(()), Err)
+  }
+
+  /// Run `effect` again against the current game, for debugging it in isolation,
+  /// e.g.: to reproduce a single turn. `path` is where the effect was recorded,
+  /// starting with the index of a top level effect then a child index at each
+  /// level. The effect is run from scratch like `apply` would, but it's given
+  /// the choices and random numbers recorded under `path`, and nothing is added
+  /// to this interpreter's effects.
+  ///
+  /// The effects themselves can't be recorded, so `effect` must be the one which
+  /// made the recording.
+  pub fn replay_subtree<T, F>(&mut self, path: &[usize], effect: F) -> Result<T, String>
+  where
+    F: for<'x> FnOnce(&mut Interpreter<'x>) -> T,
+    T: Serialize + DeserializeOwned + 'static,
+  {
+    let (&first, rest) = path
+      .split_first()
+      .ok_or_else(|| "No effect at an empty path".to_string())?;
+    let tree = self
+      .effects
+      .get(first)
+      .and_then(|tree| tree.at_path(rest))
+      .ok_or_else(|| format!("No effect at {path:?}"))?;
+
+    let interface = RecordedInterface::new(tree);
+    let mut interpreter = Interpreter::new(&mut self.game).with_interface(interface);
+    Ok(interpreter.apply(effect))
+  }
+
+  /// Save the current position under `name`, to restore to later.
+  pub fn checkpoint(&mut self, name: &str) {
+    self
+      .named_checkpoints
+      .insert(name.to_string(), self.position);
+  }
+
+  /// Rewind to the position saved under `name`, see `rewind`. Checkpoints saved
+  /// after it are discarded along with the effects they follow.
+  pub fn restore(&mut self, name: &str) -> Result<(), String> {
+    let position = *self
+      .named_checkpoints
+      .get(name)
+      .ok_or_else(|| format!("No checkpoint named {name}"))?;
+    let steps = self
+      .effects
+      .len()
+      .checked_sub(position)
+      .ok_or_else(|| format!("Checkpoint {name} is ahead of the effects applied"))?;
+
+    self.rewind(steps)?;
+    self.named_checkpoints.retain(|_, saved| *saved <= position);
+
+    Ok(())
+  }
+
+  /// Draw a random number. The draw is applied as an effect, so replays return
+  /// the recorded value instead of advancing the generator again.
+  pub(crate) fn next_u64(&mut self) -> u64 {
+    self.apply_labeled("next_u64", |int| int.interface.next_rng(&mut int.rng_seed))
+  }
+
+  /// The number of times effects labeled `label` have run, not counting replays.
+  /// `draw_card` and `gain_life` count themselves, under those names, whether or
+  /// not they were applied with a label.
+  pub fn execution_count(&self, label: &str) -> usize {
+    self.executions.get(label).copied().unwrap_or(0)
+  }
+
+  /// Count a run of the effect labeled `label`, see `execution_count`.
+  pub(crate) fn count_execution(&mut self, label: &str) {
+    *self.executions.entry(label.to_string()).or_default() += 1;
+  }
+
+  /// The choice the last top level effect stopped at, because the user interface
+  /// wasn't ready to make it, e.g.: it's waiting on a player over the network.
+  /// The effect failed with `EffectError::ChoicePending`, so to carry on, rewind
+  /// it, see `with_rewind`, and apply it again once the answer is known.
+  pub fn pending_choice(&self) -> Option<ChoiceRequest> {
+    (*self.pending_choice).clone()
   }
 
-  pub(crate) fn game_mut(&mut self) -> &mut Game {
-    self.game
+  /// Ask the user interface to make a choice. Like RNG draws, the answer is
+  /// applied as an effect so it is only asked for once. Answers outside of the
+  /// options offered are an illegal choice, and if the user interface isn't
+  /// ready to answer, the choice is left pending.
+  pub(crate) fn choose(&mut self, request: ChoiceRequest) -> Result<usize, EffectError> {
+    let options = request.options.len();
+    let index = if self.position < self.effects.len() {
+      // Replaying, so the recorded answer is returned without asking again.
+      self.apply_labeled("choose", move |int| int.interface.choose(&request))
+    } else {
+      let Some(index) = self.interface.try_choose(&request) else {
+        *self.pending_choice = Some(request);
+        return Err(EffectError::ChoicePending);
+      };
+      self.apply_labeled("choose", move |_| index)
+    };
+    if index < options {
+      Ok(index)
+    } else {
+      Err(EffectError::IllegalChoice)
+    }
+  }
+
+  /// Ask whether to do something optional, i.e.: "you may". Declining is a
+  /// choice like any other, so it's recorded and replays the same way.
+  pub(crate) fn may(&mut self, prompt: &str) -> Result<bool, EffectError> {
+    let choice = self.choose(ChoiceRequest {
+      prompt: prompt.to_string(),
+      options: vec!["Yes".to_string(), "No".to_string()],
+      min: 1,
+      max: 1,
+    })?;
+    Ok(choice == 0)
+  }
+
+  /// The game the effects are applied to.
+  pub fn game(&self) -> &Game {
+    &self.game
+  }
+
+  /// The game, for effects to change it.
+  pub fn game_mut(&mut self) -> &mut Game {
+    &mut self.game
   }
 }
+
+/// Compare two lists of sibling effects depth first, returning the first node
+/// whose result differs, or which only one of them has.
+fn first_mismatch(
+  expected: &[EffectTree],
+  actual: &[EffectTree],
+  path: &mut Vec<usize>,
+) -> Option<ReplayMismatch> {
+  for index in 0..expected.len().max(actual.len()) {
+    path.push(index);
+    let result =
+      |node: Option<&EffectTree>| node.map(|node| serde_json::to_value(&node.result).unwrap());
+    let (expected, actual) = (expected.get(index), actual.get(index));
+    if result(expected) != result(actual) {
+      return Some(ReplayMismatch {
+        path: path.clone(),
+        expected: result(expected),
+        actual: result(actual),
+      });
+    }
+    if let (Some(expected), Some(actual)) = (expected, actual) {
+      if let Some(mismatch) = first_mismatch(&expected.children, &actual.children, path) {
+        return Some(mismatch);
+      }
+    }
+    path.pop();
+  }
+  None
+}
+
+/// A single step of splitmix64, returning the next state and the output value.
+/// It is small, fast, and more than random enough for shuffling a library.
+fn splitmix64(state: u64) -> (u64, u64) {
+  let state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+  let mut z = state;
+  z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+  (state, z ^ (z >> 31))
+}
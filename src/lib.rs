@@ -1,366 +1,2892 @@
+//! A sandbox for modelling Magic: The Gathering effects as memoized functions.
+//!
+//! Effects are regular looking functions of an [`Interpreter`], and the
+//! interpreter records each result in a tree so the game can be replayed. Any
+//! crate can write its own effects:
+//!
+//! ```
+//! use std::collections::HashMap;
+//!
+//! use sandbox::{draw_card, CardData, CardId, Game, Interpreter, PlayerState};
+//!
+//! /// Lose a life for each card in hand.
+//! fn pay_for_hand(int: &mut Interpreter) -> i64 {
+//!   let game = int.game_mut();
+//!   let player = &mut game.players[game.active_player];
+//!   player.life -= player.hand.len() as i64;
+//!   player.life
+//! }
+//!
+//! let cards = HashMap::from([(
+//!   CardId(1),
+//!   CardData {
+//!     name: "Mox Awesome".to_string(),
+//!     owner: 0,
+//!     is_token: false,
+//!     mana_value: 0,
+//!     types: Vec::new(),
+//!   },
+//! )]);
+//! let mut game = Game::single_player(
+//!   PlayerState {
+//!     life: 20,
+//!     library: vec![CardId(1)],
+//!     hand: Vec::new(),
+//!     graveyard: Vec::new(),
+//!   },
+//!   cards,
+//! );
+//!
+//! let mut interpreter = Interpreter::new(&mut game);
+//! assert_eq!(interpreter.apply(draw_card), Ok("Drew Mox Awesome".to_string()));
+//! assert_eq!(interpreter.apply(pay_for_hand), 19);
+//! ```
+
 mod effect_value;
 mod interpreter;
+mod step;
 
-use std::collections::HashMap;
-#[cfg(test)]
-use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use std::{
+  collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+  fmt,
+  hash::{Hash, Hasher},
+  mem,
+};
 
-use interpreter::Interpreter;
-use serde::{Deserialize, Serialize};
+pub use effect_value::EffectTree;
+pub use interpreter::{
+  import_log, ApplyEvent, BoxedEffect, ChoiceRequest, DefaultInterface, GameInterface, Interpreter,
+  OwnedInterpreter, ReplayMismatch, SavedGame, ScriptedInterface,
+};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+pub use step::{Step, StepInterpreter};
 
-#[derive(Serialize, Deserialize)]
-pub struct Game {
-  pub life: usize,
-  pub library: Vec<String>,
-  pub hand: Vec<String>,
-  pub graveyard: Vec<String>,
+/// A stable identifier for a card object. Zones hold IDs rather than names so
+/// that effects can follow an object as it changes zones, even when a
+/// replacement effect redirects it somewhere unexpected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CardId(pub u64);
 
-  pub replacement_effects: HashMap<String, Vec<serde_json::Value>>,
+/// Everything we know about a card, independent of where it currently is. The
+/// owner is an index into `Game::players`, and determines whose library, hand
+/// and graveyard the card goes to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CardData {
+  pub name: String,
+  pub owner: usize,
+  /// Tokens aren't real cards, they cease to exist once they leave the
+  /// battlefield.
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub is_token: bool,
+  /// The card's mana value, zero for lands and tokens.
+  #[serde(default, skip_serializing_if = "is_zero")]
+  pub mana_value: u8,
+  /// Card types, e.g.: "Creature" or "Land".
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub types: Vec<String>,
 }
 
-fn handle_replacement(
-  int: &mut interpreter::Interpreter,
-  replacement_key: &str,
-) -> Option<<dyn DrawReplacement as ReplacementEffect>::Value> {
-  let game = int.game();
-
-  let alts = match game.replacement_effects.get(replacement_key) {
-    Some(alts) => alts
-      .iter()
-      .filter_map(|s| serde_json::from_value::<Box<dyn DrawReplacement>>(s.clone()).ok())
-      .filter(|eff| eff.check(game))
-      .collect::<Vec<_>>(),
-    None => Vec::new(),
-  };
-  if alts.len() == 1 {
-    // Do the alternate effect
-    return Some(alts[0].apply(int));
+impl CardData {
+  pub fn is_creature(&self) -> bool {
+    self.types.iter().any(|card_type| card_type == "Creature")
   }
-  if !alts.is_empty() {
-    todo!(); // Call back into the interpreter and ask the user interface to resolve, e.g.: user choice with player determined by APNAP
+
+  /// Whether the mana value is even, which includes zero.
+  pub fn is_even_mv(&self) -> bool {
+    self.mana_value.is_multiple_of(2)
   }
-  None
 }
 
-#[cfg(test)]
-static GAIN_LIFE_CALL_COUNT: AtomicUsize = AtomicUsize::new(0);
-/// Gain life effect, it does what it says on the tin. Effects are regular
-/// looking functions.
-///
-/// We aren't addressing replacement effects here (intentionally), this is just
-/// a prototype of a React hook like "useEffect" would look like for our use
-/// case.
-pub fn gain_life(amount: usize) -> impl FnOnce(&mut interpreter::Interpreter) -> String {
-  move |int| {
-    #[cfg(test)]
-    GAIN_LIFE_CALL_COUNT.fetch_add(1, SeqCst);
-
-    let mut g = int.game_mut();
-    g.life += amount;
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+  *value == T::default()
+}
 
-    format!("Added {amount} life")
-  }
+/// The zones and life total belonging to a single player. Life can go negative,
+/// just as it does in a real game.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PlayerState {
+  pub life: i64,
+  pub library: Vec<CardId>,
+  pub hand: Vec<CardId>,
+  pub graveyard: Vec<CardId>,
 }
 
-#[cfg(test)]
-static DRAW_CARD_CALL_COUNT: AtomicUsize = AtomicUsize::new(0);
-/// Draw a single card effect.
-pub fn draw_card(int: &mut Interpreter) -> Result<String, String> {
-  #[cfg(test)]
-  DRAW_CARD_CALL_COUNT.fetch_add(1, SeqCst);
+/// The whole game state. Players are listed in turn order, and the battlefield,
+/// exile and command zone are shared by all players.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Game {
+  pub players: Vec<PlayerState>,
+  pub active_player: usize,
+  /// The phase or step of the active player's turn, see
+  /// `Interpreter::advance_phase`.
+  #[serde(default)]
+  pub phase: Phase,
+  pub battlefield: Vec<CardId>,
+  pub exile: Vec<CardId>,
+  pub command_zone: Vec<CardId>,
 
-  // Query game state for replacement effects:
-  if let Some(value) = handle_replacement(int, "DRAW") {
-    return value;
-  }
+  /// The tax owed to cast each commander from the command zone. Every card in
+  /// here is a commander, even before it has been cast.
+  #[serde(serialize_with = "serialize_sorted")]
+  pub commander_tax: HashMap<CardId, usize>,
 
-  let game = int.game_mut();
+  /// The number of mulligans each player has taken.
+  #[serde(
+    default,
+    serialize_with = "serialize_sorted",
+    skip_serializing_if = "HashMap::is_empty"
+  )]
+  pub mulligans: HashMap<usize, usize>,
 
-  if let Some(card) = game.library.pop() {
-    let message = format!("Drew {card}");
-    game.hand.push(card);
-    Ok(message)
-  } else {
-    Err("Drew from empty library! 💀".to_string())
-  }
-}
+  /// Players who have lost the game, in the order they lost.
+  pub losers: Vec<usize>,
 
-trait ReplacementEffect {
-  type Value;
+  /// Players who drew from an empty library since state-based actions were last
+  /// checked, see `Interpreter::with_lose_on_empty_draw`.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub empty_draws: Vec<usize>,
 
-  fn apply(&self, int: &mut interpreter::Interpreter) -> Self::Value;
-  fn check(&self, game: &Game) -> bool;
-}
+  /// Unspent mana by symbol, one of WUBRG for the colors or C for colorless.
+  #[serde(serialize_with = "serialize_sorted")]
+  pub mana_pool: HashMap<char, usize>,
 
-#[typetag::serde]
-trait DrawReplacement: ReplacementEffect<Value = Result<String, String>> {}
+  /// Counters on each object, by kind, e.g.: "+1/+1" or "loyalty". Kinds with no
+  /// counters left are removed, as are objects with no counters.
+  #[serde(serialize_with = "serialize_counters")]
+  pub counters: HashMap<CardId, HashMap<String, i64>>,
 
-#[derive(Serialize, Deserialize)]
-struct RandomDiscardReplacement;
+  #[serde(serialize_with = "serialize_sorted")]
+  pub cards: HashMap<CardId, CardData>,
 
-impl ReplacementEffect for RandomDiscardReplacement {
-  type Value = Result<String, String>;
+  /// Replacement effects by the key of the event they replace. This is ordered by
+  /// key, so going through the events is the same in every replay.
+  #[serde(deserialize_with = "deserialize_effects")]
+  pub replacement_effects: BTreeMap<String, Vec<serde_json::Value>>,
+  #[serde(
+    serialize_with = "serialize_sorted",
+    deserialize_with = "deserialize_effects"
+  )]
+  pub triggered_abilities: HashMap<String, Vec<serde_json::Value>>,
+  pub triggers: Vec<TriggerRequest>,
 
-  fn apply(&self, int: &mut interpreter::Interpreter) -> Self::Value {
-    let game = int.game_mut();
+  /// Each player's life total after every change to it, starting with their life
+  /// total before the first change.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub life_history: Vec<Vec<i64>>,
 
-    // We would want to run an effect against an RNG, which would be part of the
-    // "interface" of the interpreter and thus the interpreter would need a seed
-    // for determinism.
+  /// Where the current turn starts in each player's life history, see
+  /// `Game::start_turn`.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub turn_start: Vec<usize>,
 
-    // Lacking that for example's sake, we'll just discard the last card:
-    let discard = game.hand.pop().unwrap();
+  /// The number of cards drawn since the start of the turn, by any player.
+  #[serde(default, skip_serializing_if = "is_zero")]
+  pub draws_this_turn: usize,
 
-    // Replacement effects must honor the interface, e.g.: a "draw 2" is actually
-    // "draw; draw", and "mill 4" is also a repeated effect.
-    //
-    // In a worked example, we'd be working with object IDs, not strings, and that
-    // way we could handle replacement effects and interactions like Gyruda and
-    // a replacement effect like Rest in Peace. Relevant effects:
-    //
-    // Gyruda: When Gyruda enters the battlefield, each player mills four cards. Put
-    // a creature card with an even mana value from among the milled cards onto
-    // the battlefield under your control.
-    //
-    // Rest in peace: If a card or token would be put into a graveyard from
-    // anywhere, exile it instead.
-    //
-    // Even if Rest in Peace is in play, the replacement effect which moves the
-    // cards to the exile zone has the same "signature" as mill, which moves
-    // them to graveyard. Thus we can follow the object ID and Gyruda's effect
-    // resolves, the word "milled" in "among the milled cards" is generalized to
-    // whatever the replacement effect does.
-    let message = format!("Discarded {}", discard);
-    game.graveyard.push(discard);
+  /// The lowest ID `next_card_id` may give out, so IDs of objects which have
+  /// ceased to exist aren't used again.
+  #[serde(default, skip_serializing_if = "is_zero")]
+  pub next_id: u64,
+}
 
-    Ok(message)
-  }
+/// A triggered ability waiting to resolve. We skip the stack, so it resolves as
+/// soon as the top level effect which triggered it is done.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TriggerRequest {
+  pub controller: usize,
+  pub ability: serde_json::Value,
+}
+
+/// Replacement effects and triggered abilities are saved with an explicit tag,
+/// e.g.: `{"rest_in_peace": null}`, so renaming the Rust type doesn't break saved
+/// games. Games saved before the tags were given were tagged with the type name,
+/// these are the old tags and the tags they're renamed to when loaded.
+///
+/// When renaming a tag, add the old tag here too.
+const LEGACY_TAGS: &[(&str, &str)] = &[
+  ("RandomDiscardReplacement", "random_discard"),
+  ("SkipNextDrawReplacement", "skip_next_draw"),
+  ("ExileMilledCardsReplacement", "exile_milled_cards"),
+  ("RestInPeace", "rest_in_peace"),
+  ("DoubleLifeGainReplacement", "double_life_gain"),
+  ("PreventAllDamage", "prevent_all_damage"),
+  ("ReturnToCommandZone", "return_to_command_zone"),
+  ("DrawOnLifeGain", "draw_on_life_gain"),
+];
 
-  fn check(&self, game: &Game) -> bool {
-    !game.hand.is_empty()
+/// Deserialize effects by event key, renaming legacy tags, see `LEGACY_TAGS`.
+fn deserialize_effects<'de, D, M>(deserializer: D) -> Result<M, D::Error>
+where
+  D: Deserializer<'de>,
+  M: Deserialize<'de>,
+  for<'m> &'m mut M: IntoIterator<Item = (&'m String, &'m mut Vec<serde_json::Value>)>,
+{
+  let mut effects = M::deserialize(deserializer)?;
+  for (_, effects) in &mut effects {
+    for effect in effects {
+      let Some(tagged) = effect.as_object_mut() else {
+        continue;
+      };
+      for (legacy, tag) in LEGACY_TAGS {
+        if let Some(value) = tagged.remove(*legacy) {
+          tagged.insert(tag.to_string(), value);
+        }
+      }
+    }
   }
+  Ok(effects)
 }
 
-#[typetag::serde]
-impl DrawReplacement for RandomDiscardReplacement {}
+/// Serialize a HashMap in key order so snapshots of the game are stable.
+pub(crate) fn serialize_sorted<S, K, V>(
+  map: &HashMap<K, V>,
+  serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+  S: Serializer,
+  K: Serialize + Ord,
+  V: Serialize,
+{
+  map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
 
-pub fn replace_draw_with_discard(int: &mut Interpreter) {
-  let game = int.game_mut();
+/// Serialize counters in key order, both the objects and the kinds of counter.
+fn serialize_counters<S: Serializer>(
+  counters: &HashMap<CardId, HashMap<String, i64>>,
+  serializer: S,
+) -> Result<S::Ok, S::Error> {
+  counters
+    .iter()
+    .map(|(id, kinds)| (id, kinds.iter().collect::<BTreeMap<_, _>>()))
+    .collect::<BTreeMap<_, _>>()
+    .serialize(serializer)
+}
 
-  let existing = game
-    .replacement_effects
-    .entry("DRAW".to_string())
-    .or_default();
+/// The zones a card can be in. The last card of a zone's vector is the "top",
+/// e.g.: drawing pops the last card of the library.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Zone {
+  Library,
+  Hand,
+  Graveyard,
+  Exile,
+  Battlefield,
+  Command,
+}
 
-  let eff = &RandomDiscardReplacement as &dyn DrawReplacement;
-  let eff = serde_json::to_value(eff).unwrap();
-  existing.push(eff);
+impl fmt::Display for Zone {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      Zone::Library => "library",
+      Zone::Hand => "hand",
+      Zone::Graveyard => "graveyard",
+      Zone::Exile => "exile",
+      Zone::Battlefield => "battlefield",
+      Zone::Command => "command zone",
+    };
+    f.write_str(name)
+  }
 }
 
-/// Draw multiple cards. Each one calls the draw card effect.
-pub fn draw_cards(
-  count: usize,
-) -> impl FnOnce(&mut interpreter::Interpreter) -> Result<Vec<String>, String> {
-  move |int| {
-    let mut results = Vec::new();
-    for _ in 1..=count {
-      results.push(int.apply(draw_card)?);
+/// The phases and steps of a turn, in order. The beginning phase's steps and the
+/// combat phase are simplified down to a single entry each.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Phase {
+  #[default]
+  Untap,
+  Upkeep,
+  Draw,
+  Main1,
+  Combat,
+  Main2,
+  End,
+}
+
+impl Phase {
+  /// The phase after this one, the end step wraps around to the next turn.
+  pub fn next(self) -> Phase {
+    match self {
+      Phase::Untap => Phase::Upkeep,
+      Phase::Upkeep => Phase::Draw,
+      Phase::Draw => Phase::Main1,
+      Phase::Main1 => Phase::Combat,
+      Phase::Combat => Phase::Main2,
+      Phase::Main2 => Phase::End,
+      Phase::End => Phase::Untap,
     }
+  }
+}
 
-    Ok(results)
+/// The ways an effect can fail. Errors are recorded in the effect tree like any
+/// other result, so they serialize as well as display.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EffectError {
+  /// A card had to come from an empty library.
+  EmptyLibrary,
+  /// A card wasn't in the zone the effect expected to find it in.
+  CardNotFound { zone: Zone, card: String },
+  /// The user interface chose an option which wasn't offered.
+  IllegalChoice,
+  /// An effect needed more cards in a zone than there were.
+  NotEnoughCards { zone: Zone, count: usize },
+  /// The mana pool can't pay a cost, or the cost isn't one we understand.
+  CannotPayCost { cost: String },
+  /// A symbol which isn't one of WUBRG or C was given as a kind of mana.
+  UnknownManaSymbol { symbol: char },
+  /// Effects nested more than `max_depth` deep, e.g.: an event which keeps
+  /// replacing itself.
+  DepthExceeded { max_depth: usize },
+  /// A replacement effect registered under `key` couldn't be deserialized, see
+  /// `Interpreter::with_strict_replacements`.
+  MalformedReplacement {
+    key: String,
+    name: String,
+    error: String,
+  },
+  /// The user interface wasn't ready to make a choice, see
+  /// `Interpreter::pending_choice`.
+  ChoicePending,
+}
+
+impl fmt::Display for EffectError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      EffectError::EmptyLibrary => write!(f, "Library is empty! 💀"),
+      EffectError::CardNotFound { zone, card } => write!(f, "{card} is not in {zone}"),
+      EffectError::IllegalChoice => write!(f, "Chose an option which wasn't offered"),
+      EffectError::NotEnoughCards { zone, count } => {
+        write!(f, "Not enough cards in {zone}, needed {count}")
+      }
+      EffectError::CannotPayCost { cost } => write!(f, "Can't pay {cost}"),
+      EffectError::UnknownManaSymbol { symbol } => write!(f, "{symbol} is not a kind of mana"),
+      EffectError::DepthExceeded { max_depth } => {
+        write!(f, "Effects nested more than {max_depth} deep")
+      }
+      EffectError::MalformedReplacement { key, name, error } => {
+        write!(f, "Malformed {key} replacement effect {name:?}: {error}")
+      }
+      EffectError::ChoicePending => write!(f, "Waiting for a choice to be made"),
+    }
   }
 }
 
-#[cfg(test)]
-mod test {
-  use insta::{assert_json_snapshot, assert_yaml_snapshot};
+impl std::error::Error for EffectError {}
 
-  use super::*;
-  use crate::interpreter::Interpreter;
-  #[test]
-  fn it_works() {
-    // In this test we'll create a mock game state with two cards in the library,
-    // none in hand, none in graveyard.
-    //
-    // We'll then simulate a game - we could do this incrementally or all at once!
+/// Builds a single player game, leaving anything not set at its default: 20
+/// life and empty zones. Cards are given IDs in the order they're added, owned
+/// by the player.
+///
+/// ```
+/// use sandbox::{CardId, GameBuilder};
+///
+/// let game = GameBuilder::new().life(10).library(&["Forest", "Island"]).build();
+/// assert_eq!(game.players[0].life, 10);
+/// assert_eq!(game.top_of_library(0), Some(&CardId(2)));
+/// ```
+pub struct GameBuilder {
+  game: Game,
+}
 
-    let mut g = Game {
+impl GameBuilder {
+  pub fn new() -> GameBuilder {
+    let player = PlayerState {
       life: 20,
-      library: vec!["Mox Tombstone".to_string(), "Mox Awesome".to_string()],
+      library: Vec::new(),
       hand: Vec::new(),
       graveyard: Vec::new(),
-      replacement_effects: HashMap::new(),
     };
+    GameBuilder {
+      game: Game::single_player(player, HashMap::new()),
+    }
+  }
 
-    let mut interpreter = Interpreter {
-      game: &mut g,
-      effects: Vec::new(),
-      position: 0,
-    };
+  /// The player's starting life total.
+  pub fn life(mut self, life: i64) -> Self {
+    self.game.players[0].life = life;
+    self
+  }
 
-    // In our first turn we draw a card, do nothing, and we return some state just
-    // to prove that we can do so.
-    let turn_one = |int: &mut Interpreter| {
-      // Draw a single card
-      let draw_result = int.apply(draw_card);
+  /// Add cards to the library by name, from the bottom up, so the last name is
+  /// on top.
+  pub fn library(mut self, names: &[&str]) -> Self {
+    for name in names {
+      let card = self.add_card(name);
+      self.game.players[0].library.push(card);
+    }
+    self
+  }
 
-      assert_json_snapshot!(draw_result.unwrap(), @r###""Drew Mox Awesome""###);
+  /// Add cards to the hand by name.
+  pub fn hand(mut self, names: &[&str]) -> Self {
+    for name in names {
+      let card = self.add_card(name);
+      self.game.players[0].hand.push(card);
+    }
+    self
+  }
 
-      42
-    };
+  /// Add cards to the command zone by name, e.g.: to play them as commanders
+  /// with `play_commander`.
+  pub fn command_zone(mut self, names: &[&str]) -> Self {
+    for name in names {
+      let card = self.add_card(name);
+      self.game.command_zone.push(card);
+    }
+    self
+  }
 
-    // In our second turn we draw, play a card that has a static ability - a
-    // replacement effect that replaces draws with discarding.
-    let turn_two = |int: &mut Interpreter| {
-      // Use a helper method which runs a loop and draws multiple cards (each which
-      // has replacement effects applied!)
-      let draw_result = int.apply(draw_cards(1));
+  pub fn build(self) -> Game {
+    self.game
+  }
 
-      assert_json_snapshot!(draw_result.unwrap()[0], @r###""Drew Mox Tombstone""###);
+  fn add_card(&mut self, name: &str) -> CardId {
+    let id = self.game.next_card_id();
+    self.game.cards.insert(
+      id,
+      CardData {
+        name: name.to_string(),
+        owner: 0,
+        is_token: false,
+        mana_value: 0,
+        types: Vec::new(),
+      },
+    );
+    id
+  }
+}
 
-      // "Play" a card (we're skipping many steps) but, more or less, adding a
-      // replacement effect
-      int.apply(replace_draw_with_discard);
+impl Default for GameBuilder {
+  fn default() -> Self {
+    GameBuilder::new()
+  }
+}
 
-      69
-    };
+impl Game {
+  /// A game with a single player, which is all most examples need.
+  pub fn single_player(player: PlayerState, cards: HashMap<CardId, CardData>) -> Game {
+    Game {
+      players: vec![player],
+      active_player: 0,
+      phase: Phase::Untap,
+      battlefield: Vec::new(),
+      exile: Vec::new(),
+      command_zone: Vec::new(),
+      commander_tax: HashMap::new(),
+      mulligans: HashMap::new(),
+      losers: Vec::new(),
+      empty_draws: Vec::new(),
+      mana_pool: HashMap::new(),
+      counters: HashMap::new(),
+      cards,
+      replacement_effects: BTreeMap::new(),
+      triggered_abilities: HashMap::new(),
+      triggers: Vec::new(),
+      life_history: Vec::new(),
+      turn_start: Vec::new(),
+      draws_this_turn: 0,
+      next_id: 0,
+    }
+  }
 
-    // In our third turn we draw (which discards due to replacement effect) and
-    // observe that we obtained that result. We also gain some life.
-    let turn_three = |int: &mut Interpreter| {
-      // Again run our "draw cards" loop with N=1, but this time expecting a different
-      // result:
-      let draw_result = int.apply(draw_cards(1));
+  /// Players in APNAP order: the active player first, then the rest in turn
+  /// order. This is the order players make choices in when several of them need
+  /// to at the same time.
+  pub fn apnap_order(&self) -> Vec<usize> {
+    let count = self.players.len();
+    (0..count)
+      .map(|offset| (self.active_player + offset) % count)
+      .collect()
+  }
 
-      assert_json_snapshot!(draw_result.unwrap()[0], @r###""Discarded Mox Tombstone""###);
+  /// Look up the name of a card object.
+  pub fn name_of(&self, id: CardId) -> Option<&str> {
+    self.cards.get(&id).map(|card| card.name.as_str())
+  }
 
-      // Gain some life:
+  /// A readable name for effect messages, falling back to the ID for objects
+  /// missing from the registry.
+  fn describe(&self, id: CardId) -> String {
+    match self.name_of(id) {
+      Some(name) => name.to_string(),
+      None => format!("card #{}", id.0),
+    }
+  }
 
-      int.apply(gain_life(5));
-    };
+  /// A hash of the game's position, for spotting the same position reached by
+  /// different lines of play, e.g.: when searching over forked interpreters.
+  ///
+  /// Only order that can affect the game is hashed: the order of libraries
+  /// matters, as it's the order cards are drawn in, and so does the order of
+  /// graveyards, as this prototype has no other record of when cards got there.
+  /// Hands, the battlefield, exile and the command zone are unordered, so cards
+  /// in them are hashed in ID order. Maps are hashed in key order, so the hash is
+  /// the same after a serde round-trip.
+  pub fn position_hash(&self) -> u64 {
+    fn sorted(cards: &[CardId]) -> Vec<CardId> {
+      let mut cards = cards.to_vec();
+      cards.sort();
+      cards
+    }
 
-    // We'll use this later to verify that we can run the game incrementally or all
-    // at once:
-    let whole_game = |int: &mut Interpreter| {
-      int.apply(turn_one);
-      int.apply(turn_two);
-      int.apply(turn_three);
+    let mut hasher = DefaultHasher::new();
+    for player in &self.players {
+      player.life.hash(&mut hasher);
+      player.library.hash(&mut hasher);
+      sorted(&player.hand).hash(&mut hasher);
+      player.graveyard.hash(&mut hasher);
+    }
+    self.active_player.hash(&mut hasher);
+    self.phase.hash(&mut hasher);
+    sorted(&self.battlefield).hash(&mut hasher);
+    sorted(&self.exile).hash(&mut hasher);
+    sorted(&self.command_zone).hash(&mut hasher);
+    for (id, tax) in self.commander_tax.iter().collect::<BTreeMap<_, _>>() {
+      (id, tax).hash(&mut hasher);
+    }
+    self.losers.hash(&mut hasher);
+    for (player, count) in self.mulligans.iter().collect::<BTreeMap<_, _>>() {
+      (player, count).hash(&mut hasher);
+    }
+    // What happened earlier in the turn decides e.g.: whether the next draw is
+    // replaced, so it's part of the position too.
+    self.life_history.hash(&mut hasher);
+    self.turn_start.hash(&mut hasher);
+    self.draws_this_turn.hash(&mut hasher);
+    for (symbol, amount) in self.mana_pool.iter().collect::<BTreeMap<_, _>>() {
+      (symbol, amount).hash(&mut hasher);
+    }
+    for (id, kinds) in self.counters.iter().collect::<BTreeMap<_, _>>() {
+      (id, kinds.iter().collect::<BTreeMap<_, _>>()).hash(&mut hasher);
+    }
+    for (id, card) in self.cards.iter().collect::<BTreeMap<_, _>>() {
+      (
+        id,
+        &card.name,
+        card.owner,
+        card.is_token,
+        card.mana_value,
+        &card.types,
+      )
+        .hash(&mut hasher);
+    }
+    // JSON objects serialize in key order, so their text is stable.
+    for (key, effects) in &self.replacement_effects {
+      key.hash(&mut hasher);
+      for effect in effects {
+        effect.to_string().hash(&mut hasher);
+      }
+    }
+    for (key, abilities) in self.triggered_abilities.iter().collect::<BTreeMap<_, _>>() {
+      key.hash(&mut hasher);
+      for ability in abilities {
+        ability.to_string().hash(&mut hasher);
+      }
+    }
+    for trigger in &self.triggers {
+      trigger.controller.hash(&mut hasher);
+      trigger.ability.to_string().hash(&mut hasher);
+    }
+    hasher.finish()
+  }
+
+  /// An ID for a new object, e.g.: a token, which no other object has used.
+  /// The ID is taken, so the next call gives a different one, even if no object
+  /// is given this one.
+  pub fn next_card_id(&mut self) -> CardId {
+    let highest = self.cards.keys().map(|id| id.0 + 1).max().unwrap_or(1);
+    let id = highest.max(self.next_id);
+    self.next_id = id + 1;
+    CardId(id)
+  }
+
+  /// The player who owns a card. Cards missing from the registry are treated as
+  /// belonging to the active player.
+  pub fn owner_of(&self, id: CardId) -> usize {
+    self
+      .cards
+      .get(&id)
+      .map_or(self.active_player, |card| card.owner)
+  }
+
+  /// Change a player's life total, keeping track of it in their life history.
+  pub fn change_life(&mut self, player: usize, delta: i64) {
+    if self.life_history.len() < self.players.len() {
+      self.life_history.resize_with(self.players.len(), Vec::new);
+    }
+
+    let life = &mut self.players[player].life;
+    let history = &mut self.life_history[player];
+    if history.is_empty() {
+      history.push(*life);
+    }
+    *life += delta;
+    history.push(*life);
+  }
+
+  /// Mark the start of a new turn, for the rules which care about what happened
+  /// this turn.
+  pub fn start_turn(&mut self) {
+    self.turn_start = self
+      .life_history
+      .iter()
+      .map(|history| history.len().saturating_sub(1))
+      .collect();
+    self.draws_this_turn = 0;
+  }
+
+  /// The number of cards drawn this turn, e.g.: for "the second card you draw
+  /// each turn". Draws which were replaced don't count, but draws from an empty
+  /// library do, so "first draw" replacements don't apply again to the next one.
+  pub fn draws_this_turn(&self) -> usize {
+    self.draws_this_turn
+  }
+
+  /// The life a player has gained since the start of the turn. Losing life
+  /// doesn't take away from it.
+  pub fn life_gained_this_turn(&self, player: usize) -> i64 {
+    let Some(history) = self.life_history.get(player) else {
+      return 0;
     };
+    let start = self.turn_start.get(player).copied().unwrap_or(0);
+    history[start..]
+      .windows(2)
+      .map(|change| (change[1] - change[0]).max(0))
+      .sum()
+  }
 
-    // Start of game:
-    assert_yaml_snapshot!(interpreter.game(), @r###"
-    ---
-    life: 20
-    library:
-      - Mox Tombstone
-      - Mox Awesome
-    hand: []
-    graveyard: []
-    replacement_effects: {}
-    "###);
+  /// A player's zone, the shared zones are the same for every player.
+  pub fn zone(&self, player: usize, zone: Zone) -> &Vec<CardId> {
+    match zone {
+      Zone::Library => &self.players[player].library,
+      Zone::Hand => &self.players[player].hand,
+      Zone::Graveyard => &self.players[player].graveyard,
+      Zone::Exile => &self.exile,
+      Zone::Battlefield => &self.battlefield,
+      Zone::Command => &self.command_zone,
+    }
+  }
 
-    interpreter.apply(turn_one);
+  pub fn zone_mut(&mut self, player: usize, zone: Zone) -> &mut Vec<CardId> {
+    match zone {
+      Zone::Library => &mut self.players[player].library,
+      Zone::Hand => &mut self.players[player].hand,
+      Zone::Graveyard => &mut self.players[player].graveyard,
+      Zone::Exile => &mut self.exile,
+      Zone::Battlefield => &mut self.battlefield,
+      Zone::Command => &mut self.command_zone,
+    }
+  }
 
-    // Post turn one:
-    assert_yaml_snapshot!(interpreter.game(), @r###"
-    ---
-    life: 20
-    library:
-      - Mox Tombstone
-    hand:
-      - Mox Awesome
-    graveyard: []
-    replacement_effects: {}
-    "###);
+  /// The card on top of a player's library, the next one they'd draw.
+  ///
+  /// The top of the library is the end of its vector, so the bottom is the
+  /// start. Going through `top_of_library`, `push_on_top` and `bottom_card`
+  /// keeps that convention in one place.
+  pub fn top_of_library(&self, player: usize) -> Option<&CardId> {
+    self.players[player].library.last()
+  }
 
-    interpreter.apply(turn_two);
+  /// Put `card` on top of its owner's library, moving it there if it's already
+  /// in the library. A card from another zone must be taken out of it first.
+  pub fn push_on_top(&mut self, card: CardId) {
+    let owner = self.owner_of(card);
+    let library = &mut self.players[owner].library;
+    library.retain(|&c| c != card);
+    library.push(card);
+  }
 
-    // Post turn two:
-    assert_yaml_snapshot!(interpreter.game(), @r###"
-    ---
-    life: 20
-    library: []
-    hand:
-      - Mox Awesome
-      - Mox Tombstone
-    graveyard: []
-    replacement_effects:
-      DRAW:
-        - RandomDiscardReplacement: ~
-    "###);
+  /// Put `card` on the bottom of its owner's library, like `push_on_top`.
+  pub fn bottom_card(&mut self, card: CardId) {
+    let owner = self.owner_of(card);
+    let library = &mut self.players[owner].library;
+    library.retain(|&c| c != card);
+    library.insert(0, card);
+  }
 
-    interpreter.apply(turn_three);
+  /// The number of cards in one of the active player's zones, or a shared zone,
+  /// e.g.: for threshold, seven or more cards in the graveyard.
+  pub fn zone_len(&self, zone: Zone) -> usize {
+    self.zone(self.active_player, zone).len()
+  }
 
-    // Post turn three:
-    assert_yaml_snapshot!(interpreter.game(), @r###"
-    ---
-    life: 25
-    library: []
-    hand:
-      - Mox Awesome
-    graveyard:
-      - Mox Tombstone
-    replacement_effects:
-      DRAW:
-        - RandomDiscardReplacement: ~
-    "###);
+  /// The number of cards in the active player's hand, e.g.: for hellbent, or the
+  /// maximum hand size.
+  pub fn hand_size(&self) -> usize {
+    self.zone_len(Zone::Hand)
+  }
 
-    let initial_snapshot = serde_json::to_value(&interpreter).unwrap();
-    assert_eq!(GAIN_LIFE_CALL_COUNT.load(SeqCst), 1);
-    assert_eq!(DRAW_CARD_CALL_COUNT.load(SeqCst), 3);
+  /// Every card in the game with the zone it's in, e.g.: to check that no card
+  /// was lost or duplicated moving between zones.
+  pub fn all_cards(&self) -> impl Iterator<Item = (CardId, Zone)> + '_ {
+    let player_zones = (0..self.players.len())
+      .flat_map(|player| [Zone::Library, Zone::Hand, Zone::Graveyard].map(|zone| (player, zone)));
+    let shared_zones =
+      [Zone::Battlefield, Zone::Exile, Zone::Command].map(|zone| (self.active_player, zone));
+    player_zones
+      .chain(shared_zones)
+      .flat_map(move |(player, zone)| {
+        self
+          .zone(player, zone)
+          .iter()
+          .map(move |&card| (card, zone))
+      })
+  }
 
-    // Re-run the interpreter, but re-use all existing effects. This won't actually
-    // call any of the functions, but each effect's _result_ will be returned
-    // from "apply" functions. Since all of these are deterministic, we can rapidly
-    // "replay" the game up to the current decision point.
+  /// Check that there are `expected_total` cards across all zones, not counting
+  /// tokens, which come and go. An effect which drops or duplicates a card
+  /// breaks this.
+  pub fn assert_conservation(&self, expected_total: usize) -> Result<(), String> {
+    let total = self.non_token_count();
+    if total == expected_total {
+      Ok(())
+    } else {
+      Err(format!(
+        "Expected {expected_total} cards, but found {total}"
+      ))
+    }
+  }
 
-    // Even better, as effects are trees, we can represent the game as a series of
-    // arbitrarily high level effects to obtain performance improvements or to
-    // "skip ahead", e.g.: skip to the current player's turn and run the game
-    // forward from that point.
-    let effects = interpreter.effects;
+  /// The number of cards across all zones, not counting tokens.
+  pub(crate) fn non_token_count(&self) -> usize {
+    self
+      .all_cards()
+      .filter(|(card, _)| self.cards.get(card).is_none_or(|card| !card.is_token))
+      .count()
+  }
+}
 
-    let mut interpreter = Interpreter {
-      game: &mut g,
-      // Re-use prior effects to prove idempotency.
-      effects,
-      position: 0,
-    };
+/// What changed between two game states, see `Game::diff`.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameDiff {
+  /// The change in each player's life total, for players whose life changed.
+  pub life: Vec<(usize, i64)>,
+  /// The zones cards entered or left.
+  pub zones: Vec<ZoneDiff>,
+}
 
-    whole_game(&mut interpreter);
-    assert_eq!(GAIN_LIFE_CALL_COUNT.load(SeqCst), 1);
-    assert_eq!(DRAW_CARD_CALL_COUNT.load(SeqCst), 3);
+/// Cards which entered or left a zone. The player is `None` for the shared
+/// zones, the battlefield, exile and the command zone.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ZoneDiff {
+  pub player: Option<usize>,
+  pub zone: Zone,
+  pub added: Vec<String>,
+  pub removed: Vec<String>,
+}
 
-    let final_snapshot = serde_json::to_value(&interpreter).unwrap();
+impl Game {
+  /// Compare this game with a later state of it, e.g.: to see what an effect
+  /// did when debugging a replay.
+  pub fn diff(&self, other: &Game) -> GameDiff {
+    let mut diff = GameDiff::default();
 
-    assert_eq!(initial_snapshot, final_snapshot);
-    assert_yaml_snapshot!(interpreter, @r###"
-    ---
-    game:
-      life: 25
-      library: []
-      hand:
-        - Mox Awesome
-      graveyard:
-        - Mox Tombstone
-      replacement_effects:
-        DRAW:
-          - RandomDiscardReplacement: ~
-    effects:
-      - result: 42
-        children:
-          - result:
-              Ok: Drew Mox Awesome
-            children: []
-      - result: 69
-        children:
-          - result:
-              Ok:
-                - Drew Mox Tombstone
-            children:
-              - result:
-                  Ok: Drew Mox Tombstone
+    for (player, (before, after)) in self.players.iter().zip(&other.players).enumerate() {
+      if before.life != after.life {
+        diff.life.push((player, after.life - before.life));
+      }
+    }
+
+    let player_zones = (0..self.players.len().min(other.players.len())).flat_map(|player| {
+      [Zone::Library, Zone::Hand, Zone::Graveyard].map(|zone| (Some(player), zone))
+    });
+    let shared_zones = [Zone::Battlefield, Zone::Exile, Zone::Command].map(|zone| (None, zone));
+    for (player, zone) in player_zones.chain(shared_zones) {
+      let before = self.zone(player.unwrap_or(self.active_player), zone);
+      let after = other.zone(player.unwrap_or(other.active_player), zone);
+
+      let added = after
+        .iter()
+        .filter(|card| !before.contains(card))
+        .map(|&card| other.describe(card))
+        .collect::<Vec<_>>();
+      let removed = before
+        .iter()
+        .filter(|card| !after.contains(card))
+        .map(|&card| self.describe(card))
+        .collect::<Vec<_>>();
+      if !added.is_empty() || !removed.is_empty() {
+        diff.zones.push(ZoneDiff {
+          player,
+          zone,
+          added,
+          removed,
+        });
+      }
+    }
+
+    diff
+  }
+}
+
+impl fmt::Display for GameDiff {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.life.is_empty() && self.zones.is_empty() {
+      return writeln!(f, "No changes");
+    }
+
+    for (player, delta) in &self.life {
+      writeln!(f, "Player {player} life: {delta:+}")?;
+    }
+    for zone in &self.zones {
+      let changes = zone
+        .added
+        .iter()
+        .map(|card| format!("+{card}"))
+        .chain(zone.removed.iter().map(|card| format!("-{card}")))
+        .collect::<Vec<_>>();
+      match zone.player {
+        Some(player) => write!(f, "Player {player} {}", zone.zone)?,
+        None => write!(f, "{}", zone.zone)?,
+      }
+      writeln!(f, ": {}", changes.join(", "))?;
+    }
+
+    Ok(())
+  }
+}
+
+/// Move a card between zones, this is the single place zone transitions happen.
+/// Library, hand and graveyard are always the owner's. Returns the name of the
+/// moved card for use in effect messages.
+///
+/// A permanent which leaves the battlefield becomes a new object, so its
+/// counters are removed.
+fn move_card(game: &mut Game, from: Zone, to: Zone, card: CardId) -> Result<String, EffectError> {
+  let owner = game.owner_of(card);
+  let source = game.zone_mut(owner, from);
+  let index = match source.iter().position(|c| *c == card) {
+    Some(index) => index,
+    None => {
+      return Err(EffectError::CardNotFound {
+        zone: from,
+        card: game.describe(card),
+      })
+    }
+  };
+  source.remove(index);
+  game.zone_mut(owner, to).push(card);
+  if from == Zone::Battlefield {
+    game.counters.remove(&card);
+  }
+  Ok(game.describe(card))
+}
+
+/// Look up the replacement effects registered under `replacement_key` and, if
+/// any apply, run one instead of the original event. Each kind of event has its
+/// own replacement trait, `R`, which fixes the signature of the effect. Fails if
+/// the user interface makes an illegal choice between several effects.
+fn handle_replacement<R>(
+  int: &mut Interpreter,
+  replacement_key: &str,
+  event: &R::Event,
+) -> Result<Option<R::Value>, EffectError>
+where
+  R: ReplacementEffect + ?Sized,
+  Box<R>: DeserializeOwned,
+{
+  if int.strict_replacements {
+    check_replacements::<R>(int.game(), replacement_key)?;
+  }
+  let mut alts = applicable::<R>(int.game(), replacement_key, event);
+  // Effects with a lower order go first, e.g.: self-replacement effects, so the
+  // user interface only chooses between the effects tied for first.
+  alts.sort_by_key(|(_, _, eff)| eff.order());
+  if let Some(first) = alts.first().map(|(_, _, eff)| eff.order()) {
+    alts.retain(|(_, _, eff)| eff.order() == first);
+  }
+  let (index, _, eff) = match alts.len() {
+    0 => return Ok(None),
+    // Do the alternate effect
+    1 => alts.swap_remove(0),
+    _ => {
+      // Call back into the interpreter and ask the user interface to resolve. With
+      // more players, the player making the choice would be determined by APNAP.
+      let choice = int.choose(ChoiceRequest {
+        prompt: format!("Choose a {replacement_key} replacement effect to apply"),
+        options: alts.iter().map(|(_, name, _)| name.clone()).collect(),
+        min: 1,
+        max: 1,
+      })?;
+      alts.swap_remove(choice)
+    }
+  };
+
+  // Spend one-shot effects before applying them, so they can't replace an event
+  // caused by their own replacement.
+  if eff.once() {
+    if let Some(alts) = int.game_mut().replacement_effects.get_mut(replacement_key) {
+      alts.remove(index);
+    }
+  }
+
+  Ok(Some(eff.apply(int, event)))
+}
+
+/// The replacement effects registered under `replacement_key` which apply to
+/// `event`, with their index in the registered effects and their name.
+fn applicable<R>(
+  game: &Game,
+  replacement_key: &str,
+  event: &R::Event,
+) -> Vec<(usize, String, Box<R>)>
+where
+  R: ReplacementEffect + ?Sized,
+  Box<R>: DeserializeOwned,
+{
+  match game.replacement_effects.get(replacement_key) {
+    Some(alts) => alts
+      .iter()
+      .enumerate()
+      .filter_map(|(index, s)| {
+        Some((
+          index,
+          replacement_name(s),
+          serde_json::from_value::<Box<R>>(s.clone()).ok()?,
+        ))
+      })
+      .filter(|(_, _, eff)| eff.check(game, event))
+      .collect(),
+    None => Vec::new(),
+  }
+}
+
+/// Check that every replacement effect registered under `replacement_key`
+/// deserializes, returning the first which doesn't.
+fn check_replacements<R>(game: &Game, replacement_key: &str) -> Result<(), EffectError>
+where
+  R: ReplacementEffect + ?Sized,
+  Box<R>: DeserializeOwned,
+{
+  for s in game
+    .replacement_effects
+    .get(replacement_key)
+    .into_iter()
+    .flatten()
+  {
+    if let Err(error) = serde_json::from_value::<Box<R>>(s.clone()) {
+      return Err(EffectError::MalformedReplacement {
+        key: replacement_key.to_string(),
+        name: replacement_name(s),
+        error: error.to_string(),
+      });
+    }
+  }
+
+  Ok(())
+}
+
+impl Game {
+  /// The names of the replacement effects which would apply to the active
+  /// player's next `event_key` event, e.g.: to warn them their draw will be
+  /// replaced. Only draws and mills can be checked without the event itself, so
+  /// for other events, e.g.: damage, this is `None` rather than a guess.
+  pub fn applicable_replacements(&self, event_key: &str) -> Option<Vec<String>> {
+    let player = self.active_player;
+    let names = match event_key {
+      "DRAW" => applicable::<dyn DrawReplacement>(self, event_key, &player)
+        .into_iter()
+        .map(|(_, name, _)| name)
+        .collect(),
+      "MILL" => applicable::<dyn MillReplacement>(self, event_key, &player)
+        .into_iter()
+        .map(|(_, name, _)| name)
+        .collect(),
+      _ => return None,
+    };
+    Some(names)
+  }
+}
+
+/// Replacement effects are stored externally tagged by typetag, so the type name
+/// is the only key of the serialized object.
+fn replacement_name(value: &serde_json::Value) -> String {
+  value
+    .as_object()
+    .and_then(|object| object.keys().next())
+    .cloned()
+    .unwrap_or_default()
+}
+
+/// Gain life effect, it does what it says on the tin. Effects are regular
+/// looking functions. The active player gains the life, see `gain_life_for`.
+pub fn gain_life(
+  amount: usize,
+) -> impl FnOnce(&mut interpreter::Interpreter) -> Result<String, EffectError> {
+  move |int| {
+    let player = int.game().active_player;
+    gain_life_for(player, amount)(int)
+  }
+}
+
+/// A player gains life. Gaining life is a replaceable event, so it can be
+/// doubled or prevented, and gaining life triggers abilities.
+pub fn gain_life_for(
+  player: usize,
+  amount: usize,
+) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError> {
+  move |int| {
+    int.count_execution("gain_life");
+
+    let event = LifeGain { player, amount };
+    if let Some(value) = handle_replacement::<dyn LifeGainReplacement>(int, "GAIN_LIFE", &event)? {
+      return Ok(value);
+    }
+
+    int.game_mut().change_life(player, amount as i64);
+    trigger_event(int, "GAIN_LIFE", player);
+
+    Ok(format!("Added {amount} life"))
+  }
+}
+
+/// Lose life. The active player loses the life, and if that brings them to zero
+/// life or below, they lose the game as a state-based action.
+pub fn lose_life(amount: usize) -> impl FnOnce(&mut Interpreter) -> String {
+  move |int| {
+    let player = int.game().active_player;
+    lose_life_for(int, player, amount);
+
+    format!("Lost {amount} life")
+  }
+}
+
+/// Deal damage to the active player. Damage is a replaceable event, so it can be
+/// prevented or redirected. Otherwise, the player loses that much life.
+pub fn deal_damage(amount: usize) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError> {
+  move |int| deal_damage_from(None, amount)(int)
+}
+
+/// The source of damage, e.g.: a creature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DamageSource {
+  pub controller: usize,
+  /// Damage dealt by a source with lifelink causes its controller to gain that
+  /// much life.
+  pub lifelink: bool,
+}
+
+/// Deal damage from `source`, if it has one, to the active player, see
+/// `deal_damage`. If the damage is dealt by a source with lifelink, its
+/// controller gains that much life as a trigger, so the life gain resolves
+/// after the damage and can itself be replaced.
+pub fn deal_damage_from(
+  source: Option<DamageSource>,
+  amount: usize,
+) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError> {
+  move |int| {
+    let player = int.game().active_player;
+
+    let event = Damage { player, amount };
+    if let Some(value) = handle_replacement::<dyn DamageReplacement>(int, "DAMAGE", &event)? {
+      return Ok(value);
+    }
+
+    lose_life_for(int, player, amount);
+
+    if let Some(DamageSource {
+      controller,
+      lifelink: true,
+    }) = source
+    {
+      let ability = &Lifelink { controller, amount } as &dyn TriggeredAbility;
+      int.queue_trigger(TriggerRequest {
+        controller,
+        ability: serde_json::to_value(ability).unwrap(),
+      });
+    }
+
+    Ok(format!("Dealt {amount} damage to player {player}"))
+  }
+}
+
+/// Take life from a player.
+fn lose_life_for(int: &mut Interpreter, player: usize, amount: usize) {
+  int.game_mut().change_life(player, -(amount as i64));
+}
+
+/// Draw a single card effect. The active player draws.
+pub fn draw_card(int: &mut Interpreter) -> Result<String, EffectError> {
+  let player = int.game().active_player;
+  draw_card_for(player)(int)
+}
+
+/// Draw a single card effect for the given player.
+pub fn draw_card_for(
+  player: usize,
+) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError> {
+  move |int| {
+    int.count_execution("draw_card");
+
+    // Query game state for replacement effects:
+    if let Some(value) = handle_replacement::<dyn DrawReplacement>(int, "DRAW", &player)? {
+      return value;
+    }
+
+    draw_from_library(int, player)
+  }
+}
+
+/// Draw the top card of a player's library, without replacement effects, e.g.:
+/// for a replacement effect which still draws the card.
+fn draw_from_library(int: &mut Interpreter, player: usize) -> Result<String, EffectError> {
+  let game = int.game_mut();
+
+  if let Some(&card) = game.top_of_library(player) {
+    let card = move_card(game, Zone::Library, Zone::Hand, card)?;
+    game.draws_this_turn += 1;
+    Ok(format!("Drew {card}"))
+  } else if int.lose_on_empty_draw {
+    let game = int.game_mut();
+    game.empty_draws.push(player);
+    game.draws_this_turn += 1;
+    Ok("Drew from an empty library".to_string())
+  } else {
+    Err(EffectError::EmptyLibrary)
+  }
+}
+
+/// Play a permanent from hand onto the battlefield. We skip casting, costs and
+/// the stack entirely, the card simply changes zones.
+pub fn play_permanent(
+  card: CardId,
+) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError> {
+  move |int| {
+    let game = int.game_mut();
+
+    let card = move_card(game, Zone::Hand, Zone::Battlefield, card)?;
+    Ok(format!("Played {card}"))
+  }
+}
+
+/// Create `count` tokens named `name` on the battlefield, owned by the active
+/// player.
+pub fn create_token(name: String, count: usize) -> impl FnOnce(&mut Interpreter) -> Vec<String> {
+  move |int| {
+    let game = int.game_mut();
+    let owner = game.active_player;
+
+    let mut results = Vec::new();
+    for _ in 0..count {
+      let id = game.next_card_id();
+      game.cards.insert(
+        id,
+        CardData {
+          name: name.clone(),
+          owner,
+          is_token: true,
+          mana_value: 0,
+          types: Vec::new(),
+        },
+      );
+      game.battlefield.push(id);
+      results.push(format!("Created {name}"));
+    }
+
+    results
+  }
+}
+
+/// Discard `count` cards from the active player's hand, choosing each one through
+/// the user interface. The cards are put into the graveyard through the usual
+/// replaceable event.
+pub fn discard(count: usize) -> impl FnOnce(&mut Interpreter) -> Result<Vec<String>, EffectError> {
+  move |int| {
+    let player = int.game().active_player;
+    if int.game().players[player].hand.len() < count {
+      return Err(EffectError::NotEnoughCards {
+        zone: Zone::Hand,
+        count,
+      });
+    }
+
+    let mut results = Vec::new();
+    for _ in 0..count {
+      let game = int.game();
+      let hand = game.players[player].hand.clone();
+      let index = int.choose(ChoiceRequest {
+        prompt: "Discard a card".to_string(),
+        options: hand.iter().map(|&card| game.describe(card)).collect(),
+        min: 1,
+        max: 1,
+      })?;
+
+      let card = put_into_graveyard(int, Zone::Hand, hand[index])?;
+      results.push(format!("Discarded {card}"));
+    }
+
+    Ok(results)
+  }
+}
+
+/// The most cards a player can keep in hand at the end of their turn.
+pub const MAX_HAND_SIZE: usize = 7;
+
+/// The cleanup step: the active player discards down to the maximum hand size,
+/// choosing the cards to discard like `discard`, so the choices are recorded.
+pub fn cleanup(int: &mut Interpreter) -> Result<Vec<String>, EffectError> {
+  let excess = int.game().hand_size().saturating_sub(MAX_HAND_SIZE);
+  if excess == 0 {
+    return Ok(Vec::new());
+  }
+
+  int.apply_try(discard(excess))
+}
+
+/// Discard the active player's whole hand, e.g.: "discard your hand, then draw
+/// seven cards". No choices are needed, and the cards are put into the graveyard
+/// through the usual replaceable event, first card in hand first.
+pub fn discard_hand(int: &mut Interpreter) -> Result<Vec<String>, EffectError> {
+  let player = int.game().active_player;
+
+  let mut results = Vec::new();
+  for card in int.game().players[player].hand.clone() {
+    let card = put_into_graveyard(int, Zone::Hand, card)?;
+    results.push(format!("Discarded {card}"));
+  }
+
+  Ok(results)
+}
+
+/// Windfall: discard your hand, then draw seven cards. Both are nested effects,
+/// so the discards and the draws are each recorded under this one, and each
+/// draw goes through the usual replaceable event.
+pub fn wheel(int: &mut Interpreter) -> Result<Vec<String>, EffectError> {
+  let mut results = int.apply_try(discard_hand)?;
+  results.extend(int.apply_try(draw_cards(7))?);
+
+  Ok(results)
+}
+
+/// Sacrifice a permanent. It's put into its owner's graveyard through the usual
+/// replaceable event, so Rest in Peace exiles it instead.
+pub fn sacrifice(card: CardId) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError> {
+  move |int| {
+    let game = int.game();
+    if !game.battlefield.contains(&card) {
+      return Err(EffectError::CardNotFound {
+        zone: Zone::Battlefield,
+        card: game.describe(card),
+      });
+    }
+
+    let card = put_into_graveyard(int, Zone::Battlefield, card)?;
+    Ok(format!("Sacrificed {card}"))
+  }
+}
+
+/// Return a permanent to its owner's hand. A bounced token goes to the hand too,
+/// then ceases to exist as a state-based action.
+pub fn bounce(card: CardId) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError> {
+  move |int| {
+    let card = move_card(int.game_mut(), Zone::Battlefield, Zone::Hand, card)?;
+    Ok(format!("Returned {card} to hand"))
+  }
+}
+
+trait ReplacementEffect {
+  type Event;
+  type Value;
+
+  fn apply(&self, int: &mut interpreter::Interpreter, event: &Self::Event) -> Self::Value;
+  fn check(&self, game: &Game, event: &Self::Event) -> bool;
+
+  /// When several replacement effects apply to the same event, those with the
+  /// lowest order apply first.
+  fn order(&self) -> i32 {
+    0
+  }
+
+  /// Whether the effect only applies the next time the event happens, e.g.: "the
+  /// next time you would draw a card". It's removed from the game once used.
+  fn once(&self) -> bool {
+    false
+  }
+}
+
+/// Replaces a player drawing a card, the event is the drawing player.
+#[typetag::serde]
+trait DrawReplacement: ReplacementEffect<Event = usize, Value = Result<String, EffectError>> {}
+
+/// Replaces a player milling a card, the event is the milling player.
+#[typetag::serde]
+trait MillReplacement: ReplacementEffect<Event = usize, Value = Result<String, EffectError>> {}
+
+/// A card that would be put into a graveyard from the given zone.
+struct ZoneChange {
+  card: CardId,
+  from: Zone,
+}
+
+#[typetag::serde]
+trait GraveyardReplacement:
+  ReplacementEffect<Event = ZoneChange, Value = Result<String, EffectError>>
+{
+}
+
+#[typetag::serde]
+trait ExileReplacement:
+  ReplacementEffect<Event = ZoneChange, Value = Result<String, EffectError>>
+{
+}
+
+#[derive(Serialize, Deserialize)]
+struct RandomDiscardReplacement;
+
+impl ReplacementEffect for RandomDiscardReplacement {
+  type Event = usize;
+  type Value = Result<String, EffectError>;
+
+  fn apply(&self, int: &mut interpreter::Interpreter, &player: &usize) -> Self::Value {
+    // Randomness comes from the interpreter, which records each draw so that
+    // replaying the game discards the same card.
+    let hand_size = int.game().players[player].hand.len() as u64;
+    let index = (int.next_u64() % hand_size) as usize;
+
+    let discard = int.game().players[player].hand[index];
+
+    // Replacement effects must honor the interface, e.g.: a "draw 2" is actually
+    // "draw; draw", and "mill 4" is also a repeated effect.
+    //
+    // We're working with object IDs, not strings, and that way we can handle
+    // replacement effects and interactions like Gyruda and a replacement effect
+    // like Rest in Peace. Relevant effects:
+    //
+    // Gyruda: When Gyruda enters the battlefield, each player mills four cards. Put
+    // a creature card with an even mana value from among the milled cards onto
+    // the battlefield under your control.
+    //
+    // Rest in peace: If a card or token would be put into a graveyard from
+    // anywhere, exile it instead.
+    //
+    // Even if Rest in Peace is in play, the replacement effect which moves the
+    // cards to the exile zone has the same "signature" as mill, which moves
+    // them to graveyard. Thus we can follow the object ID and Gyruda's effect
+    // resolves, the word "milled" in "among the milled cards" is generalized to
+    // whatever the replacement effect does.
+    let discard = put_into_graveyard(int, Zone::Hand, discard)?;
+
+    Ok(format!("Discarded {discard}"))
+  }
+
+  fn check(&self, game: &Game, &player: &usize) -> bool {
+    !game.players[player].hand.is_empty()
+  }
+}
+
+#[typetag::serde(name = "random_discard")]
+impl DrawReplacement for RandomDiscardReplacement {}
+
+/// The next time the player would draw a card, they skip that draw instead.
+#[derive(Serialize, Deserialize)]
+struct SkipNextDrawReplacement {
+  player: usize,
+}
+
+impl ReplacementEffect for SkipNextDrawReplacement {
+  type Event = usize;
+  type Value = Result<String, EffectError>;
+
+  fn apply(&self, _: &mut interpreter::Interpreter, _: &usize) -> Self::Value {
+    Ok("Skipped the draw".to_string())
+  }
+
+  fn check(&self, _: &Game, &player: &usize) -> bool {
+    player == self.player
+  }
+
+  fn once(&self) -> bool {
+    true
+  }
+}
+
+#[typetag::serde(name = "skip_next_draw")]
+impl DrawReplacement for SkipNextDrawReplacement {}
+
+/// The active player skips their next draw.
+pub fn skip_next_draw(int: &mut Interpreter) {
+  let game = int.game_mut();
+  let eff = &SkipNextDrawReplacement {
+    player: game.active_player,
+  } as &dyn DrawReplacement;
+  let eff = serde_json::to_value(eff).unwrap();
+  game
+    .replacement_effects
+    .entry("DRAW".to_string())
+    .or_default()
+    .push(eff);
+}
+
+/// Sylvan Library, simplified: if the player would draw their first card of the
+/// turn, they draw `extra` more cards along with it. Once the first card is
+/// drawn the check no longer passes, so the extra draws aren't replaced again.
+#[derive(Serialize, Deserialize)]
+struct ExtraFirstDrawReplacement {
+  player: usize,
+  extra: usize,
+}
+
+impl ReplacementEffect for ExtraFirstDrawReplacement {
+  type Event = usize;
+  type Value = Result<String, EffectError>;
+
+  fn apply(&self, int: &mut interpreter::Interpreter, &player: &usize) -> Self::Value {
+    let mut results = vec![draw_from_library(int, player)?];
+    for _ in 0..self.extra {
+      results.push(int.apply_try(draw_card_for(player))?);
+    }
+    Ok(results.join(", "))
+  }
+
+  fn check(&self, game: &Game, &player: &usize) -> bool {
+    player == self.player && game.draws_this_turn() == 0
+  }
+}
+
+#[typetag::serde(name = "extra_first_draw")]
+impl DrawReplacement for ExtraFirstDrawReplacement {}
+
+/// Sylvan Library: the active player draws two extra cards with their first
+/// draw each turn.
+pub fn play_sylvan_library(int: &mut Interpreter) {
+  let game = int.game_mut();
+  let eff = &ExtraFirstDrawReplacement {
+    player: game.active_player,
+    extra: 2,
+  } as &dyn DrawReplacement;
+  let eff = serde_json::to_value(eff).unwrap();
+  game
+    .replacement_effects
+    .entry("DRAW".to_string())
+    .or_default()
+    .push(eff);
+}
+
+pub fn replace_draw_with_discard(int: &mut Interpreter) {
+  let game = int.game_mut();
+
+  let existing = game
+    .replacement_effects
+    .entry("DRAW".to_string())
+    .or_default();
+
+  let eff = &RandomDiscardReplacement as &dyn DrawReplacement;
+  let eff = serde_json::to_value(eff).unwrap();
+  existing.push(eff);
+}
+
+/// Exile cards that would be milled, a narrow version of Rest in Peace.
+#[derive(Serialize, Deserialize)]
+struct ExileMilledCardsReplacement;
+
+impl ReplacementEffect for ExileMilledCardsReplacement {
+  type Event = usize;
+  type Value = Result<String, EffectError>;
+
+  fn apply(&self, int: &mut interpreter::Interpreter, &player: &usize) -> Self::Value {
+    // The card still leaves the top of the library, so Gyruda could follow its
+    // object ID into exile.
+    let card = *int.game().top_of_library(player).unwrap();
+    let card = put_into_exile(int, Zone::Library, card)?;
+
+    Ok(format!("Exiled {card}"))
+  }
+
+  fn check(&self, game: &Game, &player: &usize) -> bool {
+    !game.players[player].library.is_empty()
+  }
+}
+
+#[typetag::serde(name = "exile_milled_cards")]
+impl MillReplacement for ExileMilledCardsReplacement {}
+
+pub fn replace_mill_with_exile(int: &mut Interpreter) {
+  let game = int.game_mut();
+
+  let existing = game
+    .replacement_effects
+    .entry("MILL".to_string())
+    .or_default();
+
+  let eff = &ExileMilledCardsReplacement as &dyn MillReplacement;
+  let eff = serde_json::to_value(eff).unwrap();
+  existing.push(eff);
+}
+
+/// Put a card into the graveyard. Every card headed to the graveyard goes through
+/// here, so replacement effects such as Rest in Peace can send it elsewhere.
+/// Returns the name of the moved card.
+fn put_into_graveyard(
+  int: &mut Interpreter,
+  from: Zone,
+  card: CardId,
+) -> Result<String, EffectError> {
+  let event = ZoneChange { card, from };
+  if let Some(value) = handle_replacement::<dyn GraveyardReplacement>(int, "TO_GRAVEYARD", &event)?
+  {
+    return value;
+  }
+
+  move_card(int.game_mut(), from, Zone::Graveyard, card)
+}
+
+/// Exile a card. Like putting a card into the graveyard, this is a replaceable
+/// event, e.g.: so commanders can return to the command zone instead.
+fn put_into_exile(int: &mut Interpreter, from: Zone, card: CardId) -> Result<String, EffectError> {
+  let event = ZoneChange { card, from };
+  if let Some(value) = handle_replacement::<dyn ExileReplacement>(int, "TO_EXILE", &event)? {
+    return value;
+  }
+
+  move_card(int.game_mut(), from, Zone::Exile, card)
+}
+
+/// Move a card between any two zones, for zone changes without an effect of
+/// their own. Cards headed to the graveyard or exile go through the usual
+/// replaceable events, so they may end up elsewhere. Returns the name of the
+/// moved card.
+pub fn move_between(
+  card: CardId,
+  from: Zone,
+  to: Zone,
+) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError> {
+  move |int| match to {
+    Zone::Graveyard => put_into_graveyard(int, from, card),
+    Zone::Exile => put_into_exile(int, from, card),
+    _ => move_card(int.game_mut(), from, to, card),
+  }
+}
+
+/// Move several cards between two zones at once, e.g.: a board wipe, in the
+/// order given. Each card is moved like `move_between`, but nothing is moved
+/// unless every card is in `from`. Returns the IDs of the cards moved together,
+/// so a follow-up effect can refer to the group, e.g.: "among the milled cards",
+/// wherever its cards ended up.
+pub fn move_cards(
+  cards: Vec<CardId>,
+  from: Zone,
+  to: Zone,
+) -> impl FnOnce(&mut Interpreter) -> Result<Vec<CardId>, EffectError> {
+  move |int| {
+    let game = int.game();
+    if let Some(&missing) = cards
+      .iter()
+      .find(|&card| !game.zone(game.owner_of(*card), from).contains(card))
+    {
+      return Err(EffectError::CardNotFound {
+        zone: from,
+        card: game.describe(missing),
+      });
+    }
+
+    for &card in &cards {
+      move_between(card, from, to)(int)?;
+    }
+
+    Ok(cards)
+  }
+}
+
+/// Rest in Peace: If a card or token would be put into a graveyard from
+/// anywhere, exile it instead.
+#[derive(Serialize, Deserialize)]
+struct RestInPeace;
+
+impl ReplacementEffect for RestInPeace {
+  type Event = ZoneChange;
+  type Value = Result<String, EffectError>;
+
+  fn apply(&self, int: &mut interpreter::Interpreter, event: &ZoneChange) -> Self::Value {
+    put_into_exile(int, event.from, event.card)
+  }
+
+  fn check(&self, _: &Game, _: &ZoneChange) -> bool {
+    true
+  }
+}
+
+#[typetag::serde(name = "rest_in_peace")]
+impl GraveyardReplacement for RestInPeace {}
+
+pub fn play_rest_in_peace(int: &mut Interpreter) {
+  let game = int.game_mut();
+
+  let existing = game
+    .replacement_effects
+    .entry("TO_GRAVEYARD".to_string())
+    .or_default();
+
+  let eff = &RestInPeace as &dyn GraveyardReplacement;
+  let eff = serde_json::to_value(eff).unwrap();
+  existing.push(eff);
+}
+
+/// Damage about to be dealt to a player.
+struct Damage {
+  player: usize,
+  amount: usize,
+}
+
+/// Replaces damage being dealt, e.g.: to prevent or redirect it.
+#[typetag::serde]
+trait DamageReplacement: ReplacementEffect<Event = Damage, Value = String> {}
+
+/// Life about to be gained by a player.
+struct LifeGain {
+  player: usize,
+  amount: usize,
+}
+
+/// Replaces a player gaining life, e.g.: to double it.
+#[typetag::serde]
+trait LifeGainReplacement: ReplacementEffect<Event = LifeGain, Value = String> {}
+
+/// Alhammarret's Archive: If you would gain life, you gain twice that much life
+/// instead.
+#[derive(Serialize, Deserialize)]
+struct DoubleLifeGainReplacement;
+
+impl ReplacementEffect for DoubleLifeGainReplacement {
+  type Event = LifeGain;
+  type Value = String;
+
+  fn apply(&self, int: &mut interpreter::Interpreter, event: &LifeGain) -> Self::Value {
+    // Gain the life directly rather than through `gain_life_for`, which would
+    // offer the doubled gain to this replacement again, forever.
+    let amount = event.amount * 2;
+    int.game_mut().change_life(event.player, amount as i64);
+    trigger_event(int, "GAIN_LIFE", event.player);
+    format!("Added {amount} life")
+  }
+
+  fn check(&self, _: &Game, _: &LifeGain) -> bool {
+    true
+  }
+}
+
+#[typetag::serde(name = "double_life_gain")]
+impl LifeGainReplacement for DoubleLifeGainReplacement {}
+
+pub fn play_double_life(int: &mut Interpreter) {
+  let game = int.game_mut();
+
+  let existing = game
+    .replacement_effects
+    .entry("GAIN_LIFE".to_string())
+    .or_default();
+
+  let eff = &DoubleLifeGainReplacement as &dyn LifeGainReplacement;
+  let eff = serde_json::to_value(eff).unwrap();
+  existing.push(eff);
+}
+
+/// Prevent all damage that would be dealt.
+#[derive(Serialize, Deserialize)]
+struct PreventAllDamage;
+
+impl ReplacementEffect for PreventAllDamage {
+  type Event = Damage;
+  type Value = String;
+
+  fn apply(&self, _: &mut interpreter::Interpreter, event: &Damage) -> Self::Value {
+    format!(
+      "Prevented {} damage to player {}",
+      event.amount, event.player
+    )
+  }
+
+  fn check(&self, _: &Game, _: &Damage) -> bool {
+    true
+  }
+}
+
+#[typetag::serde(name = "prevent_all_damage")]
+impl DamageReplacement for PreventAllDamage {}
+
+pub fn prevent_all_damage(int: &mut Interpreter) {
+  let game = int.game_mut();
+
+  let existing = game
+    .replacement_effects
+    .entry("DAMAGE".to_string())
+    .or_default();
+
+  let eff = &PreventAllDamage as &dyn DamageReplacement;
+  let eff = serde_json::to_value(eff).unwrap();
+  existing.push(eff);
+}
+
+/// If a commander would be put into a graveyard or exile from anywhere, its
+/// owner returns it to the command zone instead.
+#[derive(Serialize, Deserialize)]
+struct ReturnToCommandZone;
+
+impl ReplacementEffect for ReturnToCommandZone {
+  type Event = ZoneChange;
+  type Value = Result<String, EffectError>;
+
+  fn apply(&self, int: &mut interpreter::Interpreter, event: &ZoneChange) -> Self::Value {
+    move_card(int.game_mut(), event.from, Zone::Command, event.card)
+  }
+
+  fn check(&self, game: &Game, event: &ZoneChange) -> bool {
+    game.commander_tax.contains_key(&event.card)
+  }
+}
+
+#[typetag::serde(name = "return_to_command_zone")]
+impl GraveyardReplacement for ReturnToCommandZone {}
+
+#[typetag::serde(name = "return_to_command_zone")]
+impl ExileReplacement for ReturnToCommandZone {}
+
+/// Play by the Commander rules: put `card` into the command zone as a commander,
+/// unless it's there already, and return commanders there instead of letting
+/// them go to the graveyard or exile.
+pub fn play_commander(card: CardId) -> impl FnOnce(&mut Interpreter) {
+  move |int| {
+    let game = int.game_mut();
+    if !game.command_zone.contains(&card) {
+      game.command_zone.push(card);
+    }
+    game.commander_tax.insert(card, 0);
+
+    // The replacement covers every commander, so it's only registered once.
+    if game.commander_tax.len() > 1 {
+      return;
+    }
+    let eff = &ReturnToCommandZone as &dyn GraveyardReplacement;
+    let eff = serde_json::to_value(eff).unwrap();
+    game
+      .replacement_effects
+      .entry("TO_GRAVEYARD".to_string())
+      .or_default()
+      .push(eff);
+
+    let eff = &ReturnToCommandZone as &dyn ExileReplacement;
+    let eff = serde_json::to_value(eff).unwrap();
+    game
+      .replacement_effects
+      .entry("TO_EXILE".to_string())
+      .or_default()
+      .push(eff);
+  }
+}
+
+/// Cast a commander from the command zone onto the battlefield. Like playing a
+/// permanent, we skip paying for it, but the tax for casting it again goes up.
+pub fn cast_from_command(
+  card: CardId,
+) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError> {
+  move |int| {
+    let game = int.game_mut();
+
+    let name = move_card(game, Zone::Command, Zone::Battlefield, card)?;
+    *game.commander_tax.entry(card).or_default() += 2;
+
+    Ok(format!("Cast {name} from the command zone"))
+  }
+}
+
+#[typetag::serde]
+trait TriggeredAbility {
+  /// The player who controls the ability.
+  fn controller(&self) -> usize;
+  /// Whether the ability triggers on an event happening to `player`.
+  fn check(&self, game: &Game, player: usize) -> bool;
+  fn resolve(&self, int: &mut Interpreter) -> Result<String, EffectError>;
+}
+
+/// Queue the abilities registered under `trigger_key` which trigger on an event
+/// happening to `player`.
+fn trigger_event(int: &mut Interpreter, trigger_key: &str, player: usize) {
+  let game = int.game();
+
+  let triggered = match game.triggered_abilities.get(trigger_key) {
+    Some(abilities) => abilities
+      .iter()
+      .filter_map(|s| {
+        let ability = serde_json::from_value::<Box<dyn TriggeredAbility>>(s.clone()).ok()?;
+        ability.check(game, player).then(|| TriggerRequest {
+          controller: ability.controller(),
+          ability: s.clone(),
+        })
+      })
+      .collect::<Vec<_>>(),
+    None => Vec::new(),
+  };
+  for trigger in triggered {
+    int.queue_trigger(trigger);
+  }
+}
+
+/// Perform state-based actions: players on zero life or below, or who drew from
+/// an empty library, lose the game, and tokens which have left the battlefield
+/// cease to exist. Each action is applied as an effect, so it's recorded in the
+/// effect tree.
+pub(crate) fn perform_state_based_actions(int: &mut Interpreter) {
+  let empty_draws = mem::take(&mut int.game_mut().empty_draws);
+  let game = int.game();
+  let losers = (0..game.players.len())
+    .filter(|player| game.players[*player].life <= 0 || empty_draws.contains(player))
+    .filter(|player| !game.losers.contains(player))
+    .collect::<Vec<_>>();
+  let mut strays = game
+    .cards
+    .iter()
+    .filter(|(id, card)| card.is_token && !game.battlefield.contains(id))
+    .map(|(&id, _)| id)
+    .collect::<Vec<_>>();
+  strays.sort();
+
+  for player in losers {
+    int.apply_labeled("state_based_action", move |int| {
+      int.game_mut().losers.push(player);
+      format!("Player {player} loses the game")
+    });
+  }
+  for token in strays {
+    int.apply_labeled("state_based_action", move |int| {
+      let game = int.game_mut();
+      for player in &mut game.players {
+        for zone in [&mut player.library, &mut player.hand, &mut player.graveyard] {
+          zone.retain(|&card| card != token);
+        }
+      }
+      game.exile.retain(|&card| card != token);
+      game.command_zone.retain(|&card| card != token);
+      let name = game.describe(token);
+      game.cards.remove(&token);
+      game.counters.remove(&token);
+      format!("{name} ceased to exist")
+    });
+  }
+}
+
+/// Resolve queued triggers in APNAP order. Resolving a trigger can queue more,
+/// so keep going until the queue is empty.
+///
+/// A player's own triggers resolve in the order their abilities were
+/// registered, rather than the player choosing an order, so ties never reach
+/// the choice callback.
+pub(crate) fn resolve_triggers(int: &mut Interpreter) {
+  while !int.game().triggers.is_empty() {
+    let game = int.game_mut();
+    let order = game.apnap_order();
+    let mut triggers = mem::take(&mut game.triggers);
+    // The sort is stable, which keeps each player's triggers in the order queued.
+    triggers.sort_by_key(|trigger| {
+      order
+        .iter()
+        .position(|&player| player == trigger.controller)
+    });
+
+    for trigger in triggers {
+      // A failing trigger doesn't stop the rest, its result is in the effect tree.
+      let _: Result<String, EffectError> = int.apply_labeled("trigger", move |int| {
+        let ability = serde_json::from_value::<Box<dyn TriggeredAbility>>(trigger.ability)
+          .expect("Queued triggers are serialized triggered abilities");
+        ability.resolve(int)
+      });
+    }
+  }
+}
+
+/// Lifelink: the controller of a source gains as much life as it dealt damage.
+/// It's only ever queued by `deal_damage_from`, never registered.
+#[derive(Serialize, Deserialize)]
+struct Lifelink {
+  controller: usize,
+  amount: usize,
+}
+
+#[typetag::serde(name = "lifelink")]
+impl TriggeredAbility for Lifelink {
+  fn controller(&self) -> usize {
+    self.controller
+  }
+
+  fn check(&self, _: &Game, _: usize) -> bool {
+    true
+  }
+
+  fn resolve(&self, int: &mut Interpreter) -> Result<String, EffectError> {
+    gain_life_for(self.controller, self.amount)(int)
+  }
+}
+
+/// Whenever you gain life, draw a card.
+#[derive(Serialize, Deserialize)]
+struct DrawOnLifeGain {
+  controller: usize,
+}
+
+#[typetag::serde(name = "draw_on_life_gain")]
+impl TriggeredAbility for DrawOnLifeGain {
+  fn controller(&self) -> usize {
+    self.controller
+  }
+
+  fn check(&self, _: &Game, player: usize) -> bool {
+    player == self.controller
+  }
+
+  fn resolve(&self, int: &mut Interpreter) -> Result<String, EffectError> {
+    draw_card_for(self.controller)(int)
+  }
+}
+
+pub fn draw_on_life_gain(int: &mut Interpreter) {
+  let game = int.game_mut();
+  let controller = game.active_player;
+
+  let existing = game
+    .triggered_abilities
+    .entry("GAIN_LIFE".to_string())
+    .or_default();
+
+  let ability = &DrawOnLifeGain { controller } as &dyn TriggeredAbility;
+  let ability = serde_json::to_value(ability).unwrap();
+  existing.push(ability);
+}
+
+/// Draw multiple cards. Each one calls the draw card effect, and the first draw
+/// that fails stops the rest.
+pub fn draw_cards(
+  count: usize,
+) -> impl FnOnce(&mut interpreter::Interpreter) -> Result<Vec<String>, EffectError> {
+  move |int| {
+    let mut results = Vec::new();
+    for _ in 1..=count {
+      results.push(int.apply_try(draw_card)?);
+    }
+
+    Ok(results)
+  }
+}
+
+/// You may draw a card. Returns `None` if the player declines.
+pub fn may_draw(int: &mut Interpreter) -> Result<Option<String>, EffectError> {
+  if !int.may("Draw a card?")? {
+    return Ok(None);
+  }
+
+  int.apply_try(draw_card).map(Some)
+}
+
+/// Draw up to `count` cards, stopping without an error once the library is
+/// empty. Other failures still stop the draws, as with `draw_cards`.
+pub fn draw_up_to(
+  count: usize,
+) -> impl FnOnce(&mut Interpreter) -> Result<Vec<String>, EffectError> {
+  move |int| {
+    let player = int.game().active_player;
+
+    let mut results = Vec::new();
+    for _ in 1..=count {
+      if int.game().players[player].library.is_empty() {
+        break;
+      }
+      results.push(int.apply_try(draw_card)?);
+    }
+
+    Ok(results)
+  }
+}
+
+/// Mill a single card, moving the top card of the active player's library to
+/// their graveyard. This has the same signature as drawing a card, so
+/// replacement effects can treat the two the same way.
+pub fn mill_one(int: &mut Interpreter) -> Result<String, EffectError> {
+  let player = int.game().active_player;
+
+  // Query game state for replacement effects:
+  if let Some(value) = handle_replacement::<dyn MillReplacement>(int, "MILL", &player)? {
+    return value;
+  }
+
+  if let Some(&card) = int.game().top_of_library(player) {
+    let card = put_into_graveyard(int, Zone::Library, card)?;
+    Ok(format!("Milled {card}"))
+  } else {
+    Err(EffectError::EmptyLibrary)
+  }
+}
+
+/// Mill multiple cards. Like drawing, this is a repeated effect, so each card is
+/// milled by the single card mill effect. If the library runs out, the cards
+/// milled up to that point are still recorded in the effect tree.
+pub fn mill(count: usize) -> impl FnOnce(&mut Interpreter) -> Result<Vec<String>, EffectError> {
+  move |int| {
+    let mut results = Vec::new();
+    for _ in 1..=count {
+      results.push(int.apply_labeled("mill_one", mill_one)?);
+    }
+
+    Ok(results)
+  }
+}
+
+/// Mill `count` cards like `mill`, returning the IDs of the cards milled, so a
+/// follow-up effect can find them wherever they ended up, e.g.: in exile with
+/// Rest in Peace.
+pub fn mill_cards(
+  count: usize,
+) -> impl FnOnce(&mut Interpreter) -> Result<Vec<CardId>, EffectError> {
+  move |int| {
+    let player = int.game().active_player;
+
+    let mut milled = Vec::new();
+    for _ in 1..=count {
+      let card = int.game().top_of_library(player).copied();
+      int.apply_labeled("mill_one", mill_one)?;
+      milled.extend(card);
+    }
+
+    Ok(milled)
+  }
+}
+
+/// Gyruda, Doom of Depths, simplified: when it enters the battlefield, its
+/// controller mills four cards, then puts the first creature card with an even
+/// mana value from among the milled cards onto the battlefield. The milled cards are
+/// followed by ID, so this works even if they were exiled instead.
+pub fn gyruda(int: &mut Interpreter) -> Result<Vec<String>, EffectError> {
+  let milled = int.apply_try(mill_cards(4))?;
+
+  let game = int.game_mut();
+  let mut results = milled
+    .iter()
+    .map(|&card| format!("Milled {}", game.describe(card)))
+    .collect::<Vec<_>>();
+
+  let even = milled
+    .into_iter()
+    .filter(|card| {
+      game
+        .cards
+        .get(card)
+        .is_some_and(|card| card.is_creature() && card.is_even_mv())
+    })
+    .find_map(|card| {
+      [Zone::Graveyard, Zone::Exile]
+        .into_iter()
+        .find(|&zone| game.zone(game.owner_of(card), zone).contains(&card))
+        .map(|zone| (card, zone))
+    });
+  if let Some((card, zone)) = even {
+    let card = move_card(game, zone, Zone::Battlefield, card)?;
+    results.push(format!("Put {card} onto the battlefield"));
+  }
+
+  Ok(results)
+}
+
+/// Reveal the top `count` cards of the active player's library, top card first,
+/// without moving them. Nothing changes, so replaying it only needs the names.
+pub fn reveal_top(count: usize) -> impl FnOnce(&mut Interpreter) -> Vec<String> {
+  move |int| {
+    let game = int.game();
+    game.players[game.active_player]
+      .library
+      .iter()
+      .rev()
+      .take(count)
+      .map(|&card| game.describe(card))
+      .collect()
+  }
+}
+
+/// Impulse draw: exile the top `count` cards of the active player's library, top
+/// card first, and they may be played from exile with `play_exiled`.
+pub fn impulse(count: usize) -> impl FnOnce(&mut Interpreter) -> Result<Vec<String>, EffectError> {
+  move |int| {
+    let player = int.game().active_player;
+    let top = int.game().players[player]
+      .library
+      .iter()
+      .rev()
+      .take(count)
+      .copied()
+      .collect::<Vec<_>>();
+
+    let mut results = Vec::new();
+    for card in top {
+      let card = put_into_exile(int, Zone::Library, card)?;
+      results.push(format!("Exiled {card}"));
+    }
+
+    Ok(results)
+  }
+}
+
+/// Play a card from exile, e.g.: one exiled by `impulse`. The user interface
+/// chooses whether it's put into its owner's hand or onto the battlefield.
+pub fn play_exiled(card: CardId) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError> {
+  move |int| {
+    let name = int.game().describe(card);
+    if !int.game().exile.contains(&card) {
+      return Err(EffectError::CardNotFound {
+        zone: Zone::Exile,
+        card: name,
+      });
+    }
+
+    let choice = int.choose(ChoiceRequest {
+      prompt: format!("Play {name} from exile"),
+      options: vec!["Hand".to_string(), "Battlefield".to_string()],
+      min: 1,
+      max: 1,
+    })?;
+    if choice == 0 {
+      let card = move_card(int.game_mut(), Zone::Exile, Zone::Hand, card)?;
+      Ok(format!("Put {card} into hand"))
+    } else {
+      let card = move_card(int.game_mut(), Zone::Exile, Zone::Battlefield, card)?;
+      Ok(format!("Played {card}"))
+    }
+  }
+}
+
+/// Cast a card from exile, e.g.: a foretold card or an adventurer. Like
+/// `play_permanent`, casting is skipped and the card goes onto the battlefield.
+pub fn cast_from_exile(
+  card: CardId,
+) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError> {
+  move |int| {
+    let card = move_card(int.game_mut(), Zone::Exile, Zone::Battlefield, card)?;
+    Ok(format!("Cast {card} from exile"))
+  }
+}
+
+/// Reveal the active player's hand to every player. Like `reveal_top`, nothing
+/// changes, so replaying it only needs the names.
+pub fn reveal_hand(int: &mut Interpreter) -> Vec<String> {
+  let game = int.game();
+  game.players[game.active_player]
+    .hand
+    .iter()
+    .map(|&card| game.describe(card))
+    .collect()
+}
+
+/// Scry: look at the top `count` cards of the library and, one at a time, choose
+/// whether each stays on top or goes to the bottom. Cards left on top keep their
+/// order. The choices are made through the interpreter, so they're recorded and
+/// replaying the scry gives the same library.
+pub fn scry(count: usize) -> impl FnOnce(&mut Interpreter) -> Result<Vec<String>, EffectError> {
+  move |int| {
+    let player = int.game().active_player;
+    let top = int.game().players[player]
+      .library
+      .iter()
+      .rev()
+      .take(count)
+      .copied()
+      .collect::<Vec<_>>();
+
+    let mut results = Vec::new();
+    for card in top {
+      let name = int.game().describe(card);
+      let choice = int.choose(ChoiceRequest {
+        prompt: format!("Scry {name}"),
+        options: vec!["Top".to_string(), "Bottom".to_string()],
+        min: 1,
+        max: 1,
+      })?;
+
+      if choice == 0 {
+        results.push(format!("Kept {name} on top"));
+      } else {
+        int.game_mut().bottom_card(card);
+        results.push(format!("Put {name} on the bottom"));
+      }
+    }
+
+    Ok(results)
+  }
+}
+
+/// Scry `scry_n`, then draw a card. Both are nested effects, so the scry choices
+/// and the draw are each recorded under this one.
+pub fn scry_then_draw(
+  scry_n: usize,
+) -> impl FnOnce(&mut Interpreter) -> Result<Vec<String>, EffectError> {
+  move |int| {
+    let mut results = int.apply_try(scry(scry_n))?;
+    results.push(int.apply_try(draw_card)?);
+
+    Ok(results)
+  }
+}
+
+/// Surveil: look at the top `count` cards of the library and, one at a time,
+/// choose whether each stays on top or is put into the graveyard. Cards are put
+/// into the graveyard through the usual replaceable event, so Rest in Peace
+/// exiles them instead.
+pub fn surveil(count: usize) -> impl FnOnce(&mut Interpreter) -> Result<Vec<String>, EffectError> {
+  move |int| {
+    let player = int.game().active_player;
+    let top = int.game().players[player]
+      .library
+      .iter()
+      .rev()
+      .take(count)
+      .copied()
+      .collect::<Vec<_>>();
+
+    let mut results = Vec::new();
+    for card in top {
+      let name = int.game().describe(card);
+      let choice = int.choose(ChoiceRequest {
+        prompt: format!("Surveil {name}"),
+        options: vec!["Top".to_string(), "Graveyard".to_string()],
+        min: 1,
+        max: 1,
+      })?;
+
+      if choice == 0 {
+        results.push(format!("Kept {name} on top"));
+      } else {
+        let name = put_into_graveyard(int, Zone::Library, card)?;
+        results.push(format!("Surveilled {name} into the graveyard"));
+      }
+    }
+
+    Ok(results)
+  }
+}
+
+/// Look at the top `count` cards of the active player's library, then decide what
+/// to do with each one, top card first, with `then`. This is the shape of scry,
+/// surveil and impulse, so custom library-top effects only need the decision.
+///
+/// The revealed cards are recorded as a "look" effect, and each decision as an
+/// effect after it, so replaying the tree shows what was seen and done.
+pub fn look_then<F>(
+  count: usize,
+  mut then: F,
+) -> impl FnOnce(&mut Interpreter) -> Result<Vec<String>, EffectError>
+where
+  F: FnMut(&mut Interpreter, CardId) -> Result<String, EffectError>,
+{
+  move |int| {
+    let player = int.game().active_player;
+    let top = int.game().players[player]
+      .library
+      .iter()
+      .rev()
+      .take(count)
+      .copied()
+      .collect::<Vec<_>>();
+
+    let names = top.iter().map(|&card| int.game().describe(card)).collect();
+    int.apply_labeled("look", move |_| -> Vec<String> { names });
+
+    let mut results = Vec::new();
+    for card in top {
+      results.push(int.apply_try(|int| then(int, card))?);
+    }
+
+    Ok(results)
+  }
+}
+
+/// Shuffle a player's library with a Fisher-Yates shuffle. Every swap is drawn
+/// from the interpreter's RNG, so a replay produces the same order.
+fn shuffle_library(int: &mut Interpreter, player: usize) {
+  for i in (1..int.game().players[player].library.len()).rev() {
+    let j = (int.next_u64() % (i as u64 + 1)) as usize;
+    int.game_mut().players[player].library.swap(i, j);
+  }
+}
+
+/// Shuffle a card from its owner's hand into their library. The shuffle draws
+/// from the interpreter's RNG, so a replay puts the card in the same place.
+pub fn shuffle_into_library(
+  card: CardId,
+) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError> {
+  move |int| {
+    let owner = int.game().owner_of(card);
+    let card = move_card(int.game_mut(), Zone::Hand, Zone::Library, card)?;
+    shuffle_library(int, owner);
+    Ok(format!("Shuffled {card} into library"))
+  }
+}
+
+/// Shuffle the active player's library.
+pub fn shuffle(int: &mut Interpreter) {
+  let player = int.game().active_player;
+  shuffle_library(int, player);
+}
+
+/// The number of cards in an opening hand.
+pub const OPENING_HAND_SIZE: usize = 7;
+
+/// Shuffle the active player's library and draw an opening hand of seven cards.
+/// Each card is drawn as usual, so draw replacements apply.
+pub fn draw_opening_hand(int: &mut Interpreter) -> Result<Vec<String>, EffectError> {
+  draw_opening_hand_of(OPENING_HAND_SIZE)(int)
+}
+
+/// Draw an opening hand of `count` cards, e.g.: for formats with smaller hands.
+pub fn draw_opening_hand_of(
+  count: usize,
+) -> impl FnOnce(&mut Interpreter) -> Result<Vec<String>, EffectError> {
+  move |int| {
+    let player = int.game().active_player;
+    shuffle_library(int, player);
+    int.apply_try(draw_cards(count))
+  }
+}
+
+/// The London mulligan: the active player shuffles their hand into their library
+/// and draws seven cards, then puts a card from their hand on the bottom of their
+/// library for each mulligan they've taken. The shuffle uses the interpreter's
+/// RNG and the cards to bottom are chosen through it, so both are recorded.
+pub fn mulligan(int: &mut Interpreter) -> Result<Vec<String>, EffectError> {
+  let player = int.game().active_player;
+
+  let game = int.game_mut();
+  for card in game.players[player].hand.clone() {
+    move_card(game, Zone::Hand, Zone::Library, card)?;
+  }
+  shuffle_library(int, player);
+
+  let mut results = vec!["Shuffled hand into library".to_string()];
+  for _ in 0..OPENING_HAND_SIZE {
+    results.push(int.apply_try(draw_card)?);
+  }
+
+  let taken = int.game_mut().mulligans.entry(player).or_default();
+  *taken += 1;
+  for _ in 0..*taken {
+    let hand = int.game().players[player].hand.clone();
+    let choice = int.choose(ChoiceRequest {
+      prompt: "Put a card from your hand on the bottom of your library".to_string(),
+      options: hand.iter().map(|&card| int.game().describe(card)).collect(),
+      min: 1,
+      max: 1,
+    })?;
+
+    let game = int.game_mut();
+    let card = game.players[player].hand.remove(choice);
+    game.bottom_card(card);
+    results.push(format!("Put {} on the bottom", int.game().describe(card)));
+  }
+
+  Ok(results)
+}
+
+/// Shuffle the active player's graveyard into their library, e.g.: Elixir of
+/// Immortality. The shuffle draws from the interpreter's RNG, so a replay ends
+/// up with the same library. Returns the names of the cards put back.
+pub fn graveyard_to_library(int: &mut Interpreter) -> Vec<String> {
+  let game = int.game_mut();
+  let player = game.active_player;
+
+  let mut results = Vec::new();
+  for card in mem::take(&mut game.players[player].graveyard) {
+    game.push_on_top(card);
+    results.push(game.describe(card));
+  }
+  shuffle_library(int, player);
+
+  results
+}
+
+/// Put `count` counters of a kind on an object. Like `remove_counter`, this
+/// never leaves a kind of counter recorded with none of it, so putting zero
+/// counters on an object doesn't change the game.
+pub fn add_counter(
+  card: CardId,
+  kind: &str,
+  count: usize,
+) -> impl FnOnce(&mut Interpreter) -> String {
+  let kind = kind.to_string();
+  move |int| {
+    let game = int.game_mut();
+    if count > 0 {
+      let counters = game.counters.entry(card).or_default();
+      *counters.entry(kind.clone()).or_default() += count as i64;
+    }
+
+    format!("Put {count} {kind} counters on {}", game.describe(card))
+  }
+}
+
+/// Remove up to `count` counters of a kind from an object. An object can't have
+/// fewer than zero counters, so this removes at most as many as there are.
+pub fn remove_counter(
+  card: CardId,
+  kind: &str,
+  count: usize,
+) -> impl FnOnce(&mut Interpreter) -> String {
+  let kind = kind.to_string();
+  move |int| {
+    let game = int.game_mut();
+
+    let mut removed = 0;
+    if let Some(counters) = game.counters.get_mut(&card) {
+      if let Some(amount) = counters.get_mut(&kind) {
+        removed = (*amount).min(count as i64);
+        *amount -= removed;
+        if *amount == 0 {
+          counters.remove(&kind);
+        }
+      }
+      if counters.is_empty() {
+        game.counters.remove(&card);
+      }
+    }
+
+    format!(
+      "Removed {removed} {kind} counters from {}",
+      game.describe(card)
+    )
+  }
+}
+
+/// The mana symbols, colorless last.
+const MANA_SYMBOLS: [char; 6] = ['W', 'U', 'B', 'R', 'G', 'C'];
+
+/// Add mana to the mana pool, of a kind given by one of WUBRG, or C for
+/// colorless.
+pub fn add_mana(
+  symbol: char,
+  amount: usize,
+) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError> {
+  move |int| {
+    if !MANA_SYMBOLS.contains(&symbol) {
+      return Err(EffectError::UnknownManaSymbol { symbol });
+    }
+    *int.game_mut().mana_pool.entry(symbol).or_default() += amount;
+    Ok(format!("Added {amount} {symbol}"))
+  }
+}
+
+/// Pay a mana cost such as "2WW" from the mana pool: a number of generic mana,
+/// followed by colored or colorless symbols. Generic mana is paid with colorless
+/// mana first, then whichever colors are most plentiful. If the cost can't be
+/// paid in full, nothing is paid.
+pub fn pay_cost(cost: &str) -> impl FnOnce(&mut Interpreter) -> Result<(), EffectError> {
+  let cost = cost.to_string();
+  move |int| {
+    let cannot_pay = || EffectError::CannotPayCost { cost: cost.clone() };
+
+    let symbols = cost.trim_start_matches(|c: char| c.is_ascii_digit());
+    let generic = match &cost[..cost.len() - symbols.len()] {
+      "" => 0,
+      digits => digits.parse::<usize>().map_err(|_| cannot_pay())?,
+    };
+
+    let mut pool = int.game().mana_pool.clone();
+    for symbol in symbols.chars() {
+      if !MANA_SYMBOLS.contains(&symbol) {
+        return Err(cannot_pay());
+      }
+      match pool.get_mut(&symbol) {
+        Some(amount) if *amount > 0 => *amount -= 1,
+        _ => return Err(cannot_pay()),
+      }
+    }
+    for _ in 0..generic {
+      let symbol = MANA_SYMBOLS
+        .into_iter()
+        .filter(|symbol| pool.get(symbol).is_some_and(|&amount| amount > 0))
+        .max_by_key(|symbol| (*symbol == 'C', pool[symbol]))
+        .ok_or_else(cannot_pay)?;
+      *pool.get_mut(&symbol).unwrap() -= 1;
+    }
+
+    pool.retain(|_, amount| *amount > 0);
+    int.game_mut().mana_pool = pool;
+    Ok(())
+  }
+}
+
+/// Regrowth: return the card named `name` from the active player's graveyard to
+/// their hand. If several cards share the name, the most recent one returns.
+pub fn regrowth(name: &str) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError> {
+  let name = name.to_string();
+  move |int| {
+    let game = int.game();
+    let found = game.players[game.active_player]
+      .graveyard
+      .iter()
+      .rev()
+      .copied()
+      .find(|&card| game.name_of(card) == Some(name.as_str()));
+
+    match found {
+      Some(card) => regrowth_card(card)(int),
+      None => Err(EffectError::CardNotFound {
+        zone: Zone::Graveyard,
+        card: name,
+      }),
+    }
+  }
+}
+
+/// Regrowth for a specific card object, returning it from its owner's graveyard
+/// to their hand.
+pub fn regrowth_card(card: CardId) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError> {
+  move |int| {
+    let card = move_card(int.game_mut(), Zone::Graveyard, Zone::Hand, card)?;
+    Ok(format!("Returned {card} to hand"))
+  }
+}
+
+/// Put a card from its owner's hand on top of their library.
+pub fn put_on_top(card: CardId) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError> {
+  move |int| {
+    let card = move_card(int.game_mut(), Zone::Hand, Zone::Library, card)?;
+    Ok(format!("Put {card} on top of library"))
+  }
+}
+
+/// Tutor: search the library for the first card, from the top, whose name
+/// matches `pred`, put it into hand, then shuffle. The predicate can't be
+/// recorded, so the search is its own effect which records the card found.
+pub fn tutor<P>(pred: P) -> impl FnOnce(&mut Interpreter) -> Result<String, EffectError>
+where
+  P: Fn(&str) -> bool + 'static,
+{
+  move |int| {
+    let player = int.game().active_player;
+    let found = int.apply_labeled("search", move |int| {
+      let game = int.game();
+      game.players[player]
+        .library
+        .iter()
+        .rev()
+        .copied()
+        .find(|&card| game.name_of(card).is_some_and(&pred))
+    });
+
+    let card = match found {
+      Some(card) => move_card(int.game_mut(), Zone::Library, Zone::Hand, card)?,
+      None => {
+        return Err(EffectError::CardNotFound {
+          zone: Zone::Library,
+          card: "A matching card".to_string(),
+        })
+      }
+    };
+    shuffle_library(int, player);
+
+    Ok(format!("Tutored {card}"))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use insta::{assert_json_snapshot, assert_snapshot, assert_yaml_snapshot};
+
+  use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+  };
+
+  use super::*;
+  use crate::interpreter::Interpreter;
+  #[test]
+  fn it_works() {
+    // In this test we'll create a mock game state with two cards in the library,
+    // none in hand, none in graveyard. Zones hold card IDs, the names live in the
+    // card registry.
+    //
+    // We'll then simulate a game - we could do this incrementally or all at once!
+
+    let mut g = GameBuilder::new()
+      .library(&["Mox Tombstone", "Mox Awesome"])
+      .build();
+
+    let mut interpreter = Interpreter::new(&mut g);
+
+    // In our first turn we draw a card, do nothing, and we return some state just
+    // to prove that we can do so.
+    let turn_one = |int: &mut Interpreter| {
+      // Draw a single card
+      let draw_result = int.apply(draw_card);
+
+      assert_json_snapshot!(draw_result.unwrap(), @r###""Drew Mox Awesome""###);
+
+      42
+    };
+
+    // In our second turn we draw, play a Mox, and play a card that has a static
+    // ability - a replacement effect that replaces draws with discarding.
+    let turn_two = |int: &mut Interpreter| {
+      // Use a helper method which runs a loop and draws multiple cards (each which
+      // has replacement effects applied!)
+      let draw_result = int.apply(draw_cards(1));
+
+      assert_json_snapshot!(draw_result.unwrap()[0], @r###""Drew Mox Tombstone""###);
+
+      let play_result = int.apply(play_permanent(CardId(2)));
+
+      assert_json_snapshot!(play_result.unwrap(), @r###""Played Mox Awesome""###);
+
+      // "Play" a card (we're skipping many steps) but, more or less, adding a
+      // replacement effect
+      int.apply(replace_draw_with_discard);
+
+      69
+    };
+
+    // In our third turn we draw (which discards due to replacement effect) and
+    // observe that we obtained that result. We also gain some life.
+    let turn_three = |int: &mut Interpreter| {
+      // Again run our "draw cards" loop with N=1, but this time expecting a different
+      // result:
+      let draw_result = int.apply(draw_cards(1));
+
+      assert_json_snapshot!(draw_result.unwrap()[0], @r###""Discarded Mox Tombstone""###);
+
+      // Gain some life:
+
+      int.apply(gain_life(5)).unwrap();
+    };
+
+    // We'll use this later to verify that we can run the game incrementally or all
+    // at once:
+    let whole_game = |int: &mut Interpreter| {
+      int.apply(turn_one);
+      int.apply(turn_two);
+      int.apply(turn_three);
+    };
+
+    // Start of game:
+    assert_yaml_snapshot!(interpreter.game(), @r###"
+    ---
+    players:
+      - life: 20
+        library:
+          - 1
+          - 2
+        hand: []
+        graveyard: []
+    active_player: 0
+    phase: Untap
+    battlefield: []
+    exile: []
+    command_zone: []
+    commander_tax: {}
+    losers: []
+    mana_pool: {}
+    counters: {}
+    cards:
+      1:
+        name: Mox Tombstone
+        owner: 0
+      2:
+        name: Mox Awesome
+        owner: 0
+    replacement_effects: {}
+    triggered_abilities: {}
+    triggers: []
+    next_id: 3
+    "###);
+
+    interpreter.apply(turn_one);
+
+    // Post turn one:
+    assert_yaml_snapshot!(interpreter.game(), @r###"
+    ---
+    players:
+      - life: 20
+        library:
+          - 1
+        hand:
+          - 2
+        graveyard: []
+    active_player: 0
+    phase: Untap
+    battlefield: []
+    exile: []
+    command_zone: []
+    commander_tax: {}
+    losers: []
+    mana_pool: {}
+    counters: {}
+    cards:
+      1:
+        name: Mox Tombstone
+        owner: 0
+      2:
+        name: Mox Awesome
+        owner: 0
+    replacement_effects: {}
+    triggered_abilities: {}
+    triggers: []
+    draws_this_turn: 1
+    next_id: 3
+    "###);
+
+    let before_turn_two = interpreter.game().clone();
+    interpreter.apply(turn_two);
+
+    assert_snapshot!(before_turn_two.diff(interpreter.game()), @r###"
+    Player 0 library: -Mox Tombstone
+    Player 0 hand: +Mox Tombstone, -Mox Awesome
+    battlefield: +Mox Awesome
+    "###);
+
+    // Post turn two:
+    assert_yaml_snapshot!(interpreter.game(), @r###"
+    ---
+    players:
+      - life: 20
+        library: []
+        hand:
+          - 1
+        graveyard: []
+    active_player: 0
+    phase: Untap
+    battlefield:
+      - 2
+    exile: []
+    command_zone: []
+    commander_tax: {}
+    losers: []
+    mana_pool: {}
+    counters: {}
+    cards:
+      1:
+        name: Mox Tombstone
+        owner: 0
+      2:
+        name: Mox Awesome
+        owner: 0
+    replacement_effects:
+      DRAW:
+        - random_discard: ~
+    triggered_abilities: {}
+    triggers: []
+    draws_this_turn: 2
+    next_id: 3
+    "###);
+
+    interpreter.apply(turn_three);
+
+    // Post turn three:
+    assert_yaml_snapshot!(interpreter.game(), @r###"
+    ---
+    players:
+      - life: 25
+        library: []
+        hand: []
+        graveyard:
+          - 1
+    active_player: 0
+    phase: Untap
+    battlefield:
+      - 2
+    exile: []
+    command_zone: []
+    commander_tax: {}
+    losers: []
+    mana_pool: {}
+    counters: {}
+    cards:
+      1:
+        name: Mox Tombstone
+        owner: 0
+      2:
+        name: Mox Awesome
+        owner: 0
+    replacement_effects:
+      DRAW:
+        - random_discard: ~
+    triggered_abilities: {}
+    triggers: []
+    life_history:
+      - - 20
+        - 25
+    draws_this_turn: 2
+    next_id: 3
+    "###);
+
+    let initial_snapshot = serde_json::to_value(&interpreter).unwrap();
+    assert_eq!(interpreter.execution_count("gain_life"), 1);
+    assert_eq!(interpreter.execution_count("draw_card"), 3);
+
+    // Re-run the interpreter, but re-use all existing effects. This won't actually
+    // call any of the functions, but each effect's _result_ will be returned
+    // from "apply" functions. Since all of these are deterministic, we can rapidly
+    // "replay" the game up to the current decision point.
+
+    // Even better, as effects are trees, we can represent the game as a series of
+    // arbitrarily high level effects to obtain performance improvements or to
+    // "skip ahead", e.g.: skip to the current player's turn and run the game
+    // forward from that point.
+    let effects = interpreter.into_effects();
+
+    // Re-use prior effects to prove idempotency.
+    let mut interpreter = Interpreter::from_effects(&mut g, effects);
+
+    whole_game(&mut interpreter);
+    assert_eq!(interpreter.execution_count("gain_life"), 0);
+    assert_eq!(interpreter.execution_count("draw_card"), 0);
+
+    let final_snapshot = serde_json::to_value(&interpreter).unwrap();
+
+    assert_eq!(initial_snapshot, final_snapshot);
+    assert_yaml_snapshot!(interpreter, @r###"
+    ---
+    game:
+      players:
+        - life: 25
+          library: []
+          hand: []
+          graveyard:
+            - 1
+      active_player: 0
+      phase: Untap
+      battlefield:
+        - 2
+      exile: []
+      command_zone: []
+      commander_tax: {}
+      losers: []
+      mana_pool: {}
+      counters: {}
+      cards:
+        1:
+          name: Mox Tombstone
+          owner: 0
+        2:
+          name: Mox Awesome
+          owner: 0
+      replacement_effects:
+        DRAW:
+          - random_discard: ~
+      triggered_abilities: {}
+      triggers: []
+      life_history:
+        - - 20
+          - 25
+      draws_this_turn: 2
+      next_id: 3
+    effects:
+      - result: 42
+        children:
+          - result:
+              Ok: Drew Mox Awesome
+            children: []
+      - result: 69
+        children:
+          - result:
+              Ok:
+                - Drew Mox Tombstone
+            children:
+              - result:
+                  Ok: Drew Mox Tombstone
                 children: []
+          - result:
+              Ok: Played Mox Awesome
+            children: []
           - result: ~
             children: []
       - result: ~
@@ -371,10 +2897,2720 @@ mod test {
             children:
               - result:
                   Ok: Discarded Mox Tombstone
-                children: []
-          - result: Added 5 life
+                children:
+                  - label: next_u64
+                    result: 16294208416658607535
+                    children: []
+                    rng_seed: 11400714819323198485
+                rng_seed: 11400714819323198485
+            rng_seed: 11400714819323198485
+          - result:
+              Ok: Added 5 life
             children: []
+        rng_seed: 11400714819323198485
     position: 3
+    rng_seed: 11400714819323198485
+    named_checkpoints: {}
+    "###);
+
+    assert_eq!(interpreter.total_nodes(), 12);
+
+    // The effects can be exported, then imported later to resume the game:
+    let log = interpreter.export_log();
+    assert_eq!(import_log(&log).unwrap(), *interpreter.effects);
+
+    let effects = interpreter.into_effects();
+    let tree: String = effects.iter().map(EffectTree::render_tree).collect();
+    assert_snapshot!(tree, @r###"
+    42
+    └─ {"Ok":"Drew Mox Awesome"}
+    69
+    ├─ {"Ok":["Drew Mox Tombstone"]}
+    │  └─ {"Ok":"Drew Mox Tombstone"}
+    ├─ {"Ok":"Played Mox Awesome"}
+    └─ null
+    null
+    ├─ {"Ok":["Discarded Mox Tombstone"]}
+    │  └─ {"Ok":"Discarded Mox Tombstone"}
+    │     └─ next_u64: 16294208416658607535
+    └─ {"Ok":"Added 5 life"}
+    "###);
+
+    let jsonl: String = effects.iter().map(EffectTree::to_jsonl).collect();
+    assert_snapshot!(jsonl, @r###"
+    {"depth":0,"label":null,"result":42}
+    {"depth":1,"label":null,"result":{"Ok":"Drew Mox Awesome"}}
+    {"depth":0,"label":null,"result":69}
+    {"depth":1,"label":null,"result":{"Ok":["Drew Mox Tombstone"]}}
+    {"depth":2,"label":null,"result":{"Ok":"Drew Mox Tombstone"}}
+    {"depth":1,"label":null,"result":{"Ok":"Played Mox Awesome"}}
+    {"depth":1,"label":null,"result":null}
+    {"depth":0,"label":null,"result":null}
+    {"depth":1,"label":null,"result":{"Ok":["Discarded Mox Tombstone"]}}
+    {"depth":2,"label":null,"result":{"Ok":"Discarded Mox Tombstone"}}
+    {"depth":3,"label":"next_u64","result":16294208416658607535}
+    {"depth":1,"label":null,"result":{"Ok":"Added 5 life"}}
+    "###);
+
+    // The recorded effects load back in, e.g.: from a saved game log.
+    let json = serde_json::to_string(&effects).unwrap();
+    let loaded: Vec<EffectTree> = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded, effects);
+
+    // Turn three's draw was replaced with a discard, which drew from the RNG:
+    let discard = effects[2].at_path(&[0, 0]).unwrap();
+    assert_eq!(
+      discard.result.get::<Result<String, EffectError>>().unwrap(),
+      Ok("Discarded Mox Tombstone".to_string())
+    );
+    assert_eq!(
+      effects[2].find_by_label("next_u64"),
+      vec![discard.at_path(&[0]).unwrap()]
+    );
+    assert!(effects[2].at_path(&[0, 1]).is_none());
+  }
+
+  #[test]
+  fn multiple_replacements_ask_once() {
+    let cards = HashMap::from([
+      (
+        CardId(1),
+        CardData {
+          name: "Mox Tombstone".to_string(),
+          owner: 0,
+          is_token: false,
+          mana_value: 0,
+          types: Vec::new(),
+        },
+      ),
+      (
+        CardId(2),
+        CardData {
+          name: "Mox Awesome".to_string(),
+          owner: 0,
+          is_token: false,
+          mana_value: 0,
+          types: Vec::new(),
+        },
+      ),
+    ]);
+    let mut g = Game::single_player(
+      PlayerState {
+        life: 20,
+        library: vec![CardId(1)],
+        hand: vec![CardId(2)],
+        graveyard: Vec::new(),
+      },
+      cards,
+    );
+
+    let asked = Rc::new(Cell::new(0));
+    let callback_asked = asked.clone();
+    let mut interpreter = Interpreter::new(&mut g).with_choice_callback(move |request| {
+      callback_asked.set(callback_asked.get() + 1);
+      assert_eq!(request.options.len(), 2);
+      1
+    });
+
+    // Two copies of the same static ability, so two replacement effects apply to
+    // the draw and the user interface has to pick one.
+    interpreter.apply(replace_draw_with_discard);
+    interpreter.apply(replace_draw_with_discard);
+    let draw_result = interpreter.apply(draw_card);
+
+    assert_json_snapshot!(draw_result.unwrap(), @r###""Discarded Mox Awesome""###);
+    assert_eq!(asked.get(), 1);
+
+    let effects = interpreter.into_effects();
+    let callback_asked = asked.clone();
+    let mut interpreter =
+      Interpreter::from_effects(&mut g, effects).with_choice_callback(move |_| {
+        callback_asked.set(callback_asked.get() + 1);
+        0
+      });
+
+    interpreter.apply(replace_draw_with_discard);
+    interpreter.apply(replace_draw_with_discard);
+    let draw_result = interpreter.apply(draw_card);
+
+    assert_json_snapshot!(draw_result.unwrap(), @r###""Discarded Mox Awesome""###);
+    assert_eq!(asked.get(), 1);
+  }
+
+  /// A game with the named cards in the library, the last name on top, and every
+  /// other zone empty.
+  fn game_with_library(names: &[&str]) -> Game {
+    GameBuilder::new().library(names).build()
+  }
+
+  #[test]
+  fn mill_four() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp", "Mountain", "Plains"]);
+
+    let mut interpreter = Interpreter::new(&mut g);
+
+    let mill_result = interpreter.apply(mill(4));
+
+    assert_yaml_snapshot!(mill_result, @r###"
+    ---
+    Ok:
+      - Milled Plains
+      - Milled Mountain
+      - Milled Swamp
+      - Milled Island
+    "###);
+    assert_yaml_snapshot!(interpreter.game(), @r###"
+    ---
+    players:
+      - life: 20
+        library:
+          - 1
+        hand: []
+        graveyard:
+          - 5
+          - 4
+          - 3
+          - 2
+    active_player: 0
+    phase: Untap
+    battlefield: []
+    exile: []
+    command_zone: []
+    commander_tax: {}
+    losers: []
+    mana_pool: {}
+    counters: {}
+    cards:
+      1:
+        name: Forest
+        owner: 0
+      2:
+        name: Island
+        owner: 0
+      3:
+        name: Swamp
+        owner: 0
+      4:
+        name: Mountain
+        owner: 0
+      5:
+        name: Plains
+        owner: 0
+    replacement_effects: {}
+    triggered_abilities: {}
+    triggers: []
+    next_id: 6
+    "###);
+
+    // Only one card left, so the second mill runs out:
+    let mill_result = interpreter.apply(mill(2));
+
+    assert_yaml_snapshot!(mill_result, @r###"Err: EmptyLibrary"###);
+  }
+
+  #[test]
+  fn mill_replaced_with_exile() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(replace_mill_with_exile);
+    let mill_result = interpreter.apply(mill(2));
+
+    assert_yaml_snapshot!(mill_result, @r###"
+    ---
+    Ok:
+      - Exiled Swamp
+      - Exiled Island
+    "###);
+    assert_eq!(interpreter.game().players[0].library, vec![CardId(1)]);
+    assert_eq!(interpreter.game().exile, vec![CardId(3), CardId(2)]);
+    assert!(interpreter.game().players[0].graveyard.is_empty());
+  }
+
+  #[test]
+  fn mill_with_rest_in_peace() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(play_rest_in_peace);
+    let mill_result = interpreter.apply(mill(2));
+
+    // The cards are still milled, they just end up in exile:
+    assert_yaml_snapshot!(mill_result, @r###"
+    ---
+    Ok:
+      - Milled Swamp
+      - Milled Island
+    "###);
+    assert_eq!(interpreter.game().exile, vec![CardId(3), CardId(2)]);
+    assert!(interpreter.game().players[0].graveyard.is_empty());
+  }
+
+  #[test]
+  fn apnap_order_starts_with_active_player() {
+    let player = || PlayerState {
+      life: 20,
+      library: Vec::new(),
+      hand: Vec::new(),
+      graveyard: Vec::new(),
+    };
+    let mut g = Game::single_player(player(), HashMap::new());
+    g.players.push(player());
+    g.players.push(player());
+
+    assert_eq!(g.apnap_order(), vec![0, 1, 2]);
+
+    g.active_player = 2;
+    assert_eq!(g.apnap_order(), vec![2, 0, 1]);
+  }
+
+  #[test]
+  fn scry_two() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+
+    // Bottom the first card, keep the second:
+    let mut answers = vec![1, 0].into_iter();
+    let mut interpreter =
+      Interpreter::new(&mut g).with_choice_callback(move |_| answers.next().unwrap());
+
+    let scry_result = interpreter.apply(scry(2));
+
+    assert_yaml_snapshot!(scry_result, @r###"
+    ---
+    Ok:
+      - Put Swamp on the bottom
+      - Kept Island on top
+    "###);
+    assert_eq!(
+      interpreter.game().players[0].library,
+      vec![CardId(3), CardId(1), CardId(2)]
+    );
+  }
+
+  #[test]
+  fn rewind_draws() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp", "Mountain"]);
+
+    let mut interpreter = Interpreter::new(&mut g).with_rewind();
+
+    for _ in 0..3 {
+      interpreter.apply(draw_card).unwrap();
+    }
+    assert_eq!(interpreter.game().players[0].hand.len(), 3);
+
+    // Undo the last two draws, and mill instead:
+    interpreter.rewind(2).unwrap();
+    assert_eq!(interpreter.game().players[0].hand, vec![CardId(4)]);
+
+    let mill_result = interpreter.apply(mill(1));
+
+    assert_yaml_snapshot!(mill_result, @r###"
+    ---
+    Ok:
+      - Milled Swamp
+    "###);
+    assert_eq!(interpreter.effects.len(), 2);
+    assert_eq!(interpreter.game().players[0].hand, vec![CardId(4)]);
+    assert_eq!(interpreter.game().players[0].graveyard, vec![CardId(3)]);
+
+    // Can't rewind further than the start of the game:
+    assert!(interpreter.rewind(3).is_err());
+  }
+
+  #[test]
+  fn rewind_needs_checkpoints() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(draw_card).unwrap();
+
+    assert_eq!(
+      interpreter.rewind(1),
+      Err("Cannot rewind without with_rewind".to_string())
+    );
+    assert_eq!(interpreter.game().players[0].hand, vec![CardId(2)]);
+  }
+
+  #[test]
+  fn failed_rewind_keeps_checkpoint() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let mut interpreter = Interpreter::new(&mut g).with_rewind();
+    interpreter.apply(draw_card).unwrap();
+
+    let checkpoint = interpreter.checkpoints.as_mut().unwrap()[0]
+      .as_mut()
+      .unwrap();
+    let game = mem::take(&mut checkpoint.game);
+    assert!(interpreter.rewind(1).is_err());
+    assert_eq!(interpreter.effects.len(), 1);
+
+    interpreter.checkpoints.as_mut().unwrap()[0]
+      .as_mut()
+      .unwrap()
+      .game = game;
+    interpreter.rewind(1).unwrap();
+    assert!(interpreter.game().players[0].hand.is_empty());
+  }
+
+  #[test]
+  fn surveil_three() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp", "Mountain"]);
+
+    // Keep the first card, bin the second, keep the third:
+    let mut answers = vec![0, 1, 0].into_iter();
+    let mut interpreter =
+      Interpreter::new(&mut g).with_choice_callback(move |_| answers.next().unwrap());
+
+    let surveil_result = interpreter.apply(surveil(3));
+
+    assert_yaml_snapshot!(surveil_result, @r###"
+    ---
+    Ok:
+      - Kept Mountain on top
+      - Surveilled Swamp into the graveyard
+      - Kept Island on top
+    "###);
+    assert_eq!(
+      interpreter.game().players[0].library,
+      vec![CardId(1), CardId(2), CardId(4)]
+    );
+    assert_eq!(interpreter.game().players[0].graveyard, vec![CardId(3)]);
+  }
+
+  #[test]
+  fn draw_from_empty_library_aborts() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+
+    let mut interpreter = Interpreter::new(&mut g);
+
+    let draw_result = interpreter.apply(draw_cards(3));
+
+    assert_eq!(draw_result, Err(EffectError::EmptyLibrary));
+    assert_eq!(interpreter.game().players[0].hand.len(), 2);
+    // The successful draws are still recorded, along with the failure:
+    assert_yaml_snapshot!(*interpreter.effects, @r###"
+    ---
+    - result:
+        Err: EmptyLibrary
+      children:
+        - result:
+            Ok: Drew Island
+          children: []
+        - result:
+            Ok: Drew Forest
+          children: []
+        - result:
+            Err: EmptyLibrary
+          children: []
+    "###);
+  }
+
+  #[test]
+  fn gain_life_triggers_draw() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(draw_on_life_gain);
+    interpreter.apply(gain_life(5)).unwrap();
+
+    assert_eq!(interpreter.game().players[0].hand, vec![CardId(2)]);
+    // The trigger resolves as part of the effect which triggered it:
+    assert_yaml_snapshot!(*interpreter.effects, @r###"
+    ---
+    - result: ~
+      children: []
+    - result:
+        Ok: Added 5 life
+      children:
+        - label: trigger
+          result:
+            Ok: Drew Island
+          children: []
+    "###);
+
+    // Replaying doesn't fire the trigger a second time:
+    let effects = interpreter.into_effects();
+    let mut interpreter = Interpreter::from_effects(&mut g, effects);
+
+    interpreter.apply(draw_on_life_gain);
+    interpreter.apply(gain_life(5)).unwrap();
+
+    assert_eq!(interpreter.game().players[0].hand, vec![CardId(2)]);
+    assert!(interpreter.game().triggers.is_empty());
+  }
+
+  #[test]
+  fn sacrifice_with_and_without_rest_in_peace() {
+    let mut g = game_with_library(&["Mox Tombstone", "Mox Awesome"]);
+
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(draw_cards(2)).unwrap();
+    interpreter.apply(play_permanent(CardId(1))).unwrap();
+    interpreter.apply(play_permanent(CardId(2))).unwrap();
+
+    let sacrifice_result = interpreter.apply(sacrifice(CardId(1)));
+    assert_yaml_snapshot!(sacrifice_result, @r###"Ok: Sacrificed Mox Tombstone"###);
+    assert_eq!(interpreter.game().players[0].graveyard, vec![CardId(1)]);
+
+    // It's gone now, so it can't be sacrificed again:
+    let sacrifice_result = interpreter.apply(sacrifice(CardId(1)));
+    assert_yaml_snapshot!(sacrifice_result, @r###"
+    ---
+    Err:
+      CardNotFound:
+        zone: Battlefield
+        card: Mox Tombstone
+    "###);
+
+    interpreter.apply(play_rest_in_peace);
+    let sacrifice_result = interpreter.apply(sacrifice(CardId(2)));
+    assert_yaml_snapshot!(sacrifice_result, @r###"Ok: Sacrificed Mox Awesome"###);
+    assert_eq!(interpreter.game().exile, vec![CardId(2)]);
+    assert_eq!(interpreter.game().players[0].graveyard, vec![CardId(1)]);
+    assert!(interpreter.game().battlefield.is_empty());
+  }
+
+  #[test]
+  fn tutor_for_a_mox() {
+    let mut g = game_with_library(&["Forest", "Mox Tombstone", "Island", "Mox Awesome", "Swamp"]);
+
+    let mut interpreter = Interpreter::new(&mut g);
+
+    let tutor_result = interpreter.apply(tutor(|name| name.starts_with("Mox")));
+
+    // Mox Awesome is nearer the top:
+    assert_yaml_snapshot!(tutor_result, @r###"Ok: Tutored Mox Awesome"###);
+    assert_eq!(interpreter.game().players[0].hand, vec![CardId(4)]);
+    assert_eq!(interpreter.game().players[0].library.len(), 4);
+
+    let tutor_result = interpreter.apply(tutor(|name| name.starts_with("Black Lotus")));
+    assert_yaml_snapshot!(tutor_result, @r###"
+    ---
+    Err:
+      CardNotFound:
+        zone: Library
+        card: A matching card
+    "###);
+  }
+
+  #[test]
+  fn shuffle_replays_identically() {
+    let names = ["Forest", "Island", "Swamp", "Mountain", "Plains"];
+    let mut g = game_with_library(&names);
+
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(shuffle);
+
+    let library = interpreter.game().players[0].library.clone();
+    assert_yaml_snapshot!(library, @r###"
+    ---
+    - 3
+    - 4
+    - 2
+    - 5
+    - 1
+    "###);
+
+    // Replaying the recorded effects leaves the library alone:
+    let initial_snapshot = serde_json::to_value(&interpreter).unwrap();
+    let effects = interpreter.into_effects();
+    let mut interpreter = Interpreter::from_effects(&mut g, effects);
+
+    interpreter.apply(shuffle);
+
+    assert_eq!(interpreter.game().players[0].library, library);
+    assert_eq!(
+      serde_json::to_value(&interpreter).unwrap(),
+      initial_snapshot
+    );
+
+    // And running it from scratch with the same seed gives the same order:
+    let mut g = game_with_library(&names);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(shuffle);
+
+    assert_eq!(interpreter.game().players[0].library, library);
+  }
+
+  #[test]
+  fn effect_values_round_trip() {
+    use crate::effect_value::{Backend, Binary, EffectValue, Json};
+
+    fn round_trip<B: Backend>() {
+      let drawn: Result<Vec<String>, String> = Ok(vec!["Drew Mox Awesome".to_string()]);
+      let repr = B::encode(&drawn).unwrap();
+      assert_eq!(
+        B::decode::<Result<Vec<String>, String>>(&repr).unwrap(),
+        drawn
+      );
+
+      let found = Some(CardId(u64::MAX));
+      let repr = B::encode(&found).unwrap();
+      assert_eq!(B::decode::<Option<CardId>>(&repr).unwrap(), found);
+
+      let changes: (i64, f64, bool, ()) = (-20, 0.5, true, ());
+      let repr = B::encode(&changes).unwrap();
+      assert_eq!(B::decode::<(i64, f64, bool, ())>(&repr).unwrap(), changes);
+
+      // Either way, saving as JSON writes the value itself.
+      assert_eq!(
+        serde_json::to_value(&repr).unwrap(),
+        serde_json::json!([-20, 0.5, true, null])
+      );
+    }
+
+    round_trip::<Json>();
+    round_trip::<Binary>();
+
+    // The bytes are smaller than even the JSON text, let alone a JSON value.
+    let drawn: Result<Vec<String>, EffectError> = Ok(vec!["Drew Mox Awesome".to_string(); 3]);
+    let json = serde_json::to_string(&drawn).unwrap();
+    let bytes = Binary::encode(&drawn).unwrap();
+    assert!(bytes.0.len() < json.len());
+
+    // And whichever backend is enabled, through the effect value itself:
+    let value = EffectValue::new(&(CardId(7), "Drew Mox Tombstone".to_string())).unwrap();
+    assert_eq!(
+      value.get::<(CardId, String)>().unwrap(),
+      (CardId(7), "Drew Mox Tombstone".to_string())
+    );
+  }
+
+  #[test]
+  fn deal_damage_with_and_without_prevention() {
+    let mut g = game_with_library(&[]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    assert_json_snapshot!(interpreter.apply(deal_damage(25)), @r###"
+    ---
+    {
+      "Ok": "Dealt 25 damage to player 0"
+    }
+    "###);
+    assert_eq!(interpreter.game().players[0].life, -5);
+
+    // The loss is a state-based action, recorded as part of the damage:
+    assert_yaml_snapshot!(interpreter.into_effects(), @r###"
+    ---
+    - result:
+        Ok: Dealt 25 damage to player 0
+      children:
+        - label: state_based_action
+          result: Player 0 loses the game
+          children: []
+    "###);
+
+    let mut g = game_with_library(&[]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(prevent_all_damage);
+    assert_json_snapshot!(interpreter.apply(deal_damage(25)), @r###"
+    ---
+    {
+      "Ok": "Prevented 25 damage to player 0"
+    }
+    "###);
+    assert_eq!(interpreter.game().players[0].life, 20);
+    assert!(interpreter.game().triggers.is_empty());
+  }
+
+  #[test]
+  fn lose_more_life_than_available() {
+    let mut g = game_with_library(&[]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(lose_life(15));
+    assert_eq!(interpreter.game().players[0].life, 5);
+    assert!(interpreter.game().triggers.is_empty());
+
+    assert_json_snapshot!(interpreter.apply(lose_life(7)), @r###""Lost 7 life""###);
+    assert_eq!(interpreter.game().players[0].life, -2);
+
+    assert_yaml_snapshot!(interpreter.into_effects(), @r###"
+    ---
+    - result: Lost 15 life
+      children: []
+    - result: Lost 7 life
+      children:
+        - label: state_based_action
+          result: Player 0 loses the game
+          children: []
+    "###);
+  }
+
+  #[test]
+  fn reveal_top_two() {
+    let mut g = game_with_library(&["Mox Tombstone", "Mox Awesome", "Mox Lotus"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    let revealed = interpreter.apply(reveal_top(2));
+
+    assert_json_snapshot!(revealed, @r###"
+    ---
+    [
+      "Mox Lotus",
+      "Mox Awesome"
+    ]
+    "###);
+    assert_eq!(
+      interpreter.game().players[0].library,
+      vec![CardId(1), CardId(2), CardId(3)]
+    );
+  }
+
+  #[test]
+  fn scry_rejects_illegal_choice() {
+    let mut g = game_with_library(&["Swamp", "Island"]);
+    let mut interpreter = Interpreter::new(&mut g).with_choice_callback(|_| 2);
+
+    assert_eq!(interpreter.apply(scry(1)), Err(EffectError::IllegalChoice));
+    assert_eq!(
+      interpreter.game().players[0].library,
+      vec![CardId(1), CardId(2)]
+    );
+  }
+
+  #[test]
+  fn fork_plays_a_different_turn_three() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(draw_cards(2)).unwrap();
+
+    // In the fork, turn three mills instead of drawing:
+    let mut fork = interpreter.fork();
+    assert_eq!(fork.apply(mill(1)), Ok(vec!["Milled Forest".to_string()]));
+    assert_eq!(fork.game().players[0].graveyard, vec![CardId(1)]);
+
+    // The original is untouched, and plays its own turn three:
+    assert!(interpreter.game().players[0].graveyard.is_empty());
+    assert_eq!(interpreter.game().players[0].library, vec![CardId(1)]);
+    assert_eq!(interpreter.apply(draw_card), Ok("Drew Forest".to_string()));
+    assert_eq!(interpreter.game().players[0].hand.len(), 3);
+
+    assert_yaml_snapshot!(fork.into_effects(), @r###"
+    ---
+    - result:
+        Ok:
+          - Drew Swamp
+          - Drew Island
+      children:
+        - result:
+            Ok: Drew Swamp
+          children: []
+        - result:
+            Ok: Drew Island
+          children: []
+    - result:
+        Ok:
+          - Milled Forest
+      children:
+        - label: mill_one
+          result:
+            Ok: Milled Forest
+          children: []
+    "###);
+  }
+
+  #[test]
+  fn draw_up_to_stops_at_empty_library() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    let draw_result = interpreter.apply(draw_up_to(5));
+
+    assert_yaml_snapshot!(draw_result, @r###"
+    ---
+    Ok:
+      - Drew Island
+      - Drew Forest
+    "###);
+    assert!(interpreter.game().players[0].library.is_empty());
+  }
+
+  /// A draw replacement which reports its order, to see which one applied.
+  #[derive(Serialize, Deserialize)]
+  struct OrderedDrawReplacement {
+    order: i32,
+  }
+
+  impl ReplacementEffect for OrderedDrawReplacement {
+    type Event = usize;
+    type Value = Result<String, EffectError>;
+
+    fn apply(&self, _: &mut Interpreter, _: &usize) -> Self::Value {
+      Ok(format!("Replaced by order {}", self.order))
+    }
+
+    fn check(&self, _: &Game, _: &usize) -> bool {
+      true
+    }
+
+    fn order(&self) -> i32 {
+      self.order
+    }
+  }
+
+  #[typetag::serde]
+  impl DrawReplacement for OrderedDrawReplacement {}
+
+  #[test]
+  fn ordered_replacements_apply_lowest_first() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    for order in [1, -1, 1] {
+      let eff = &OrderedDrawReplacement { order } as &dyn DrawReplacement;
+      g.replacement_effects
+        .entry("DRAW".to_string())
+        .or_default()
+        .push(serde_json::to_value(eff).unwrap());
+    }
+
+    // Only one effect has the lowest order, so there's nothing to choose:
+    let mut interpreter = Interpreter::new(&mut g);
+    let draw_result = interpreter.apply(draw_cards(2));
+
+    assert_yaml_snapshot!(draw_result, @r###"
+    ---
+    Ok:
+      - Replaced by order -1
+      - Replaced by order -1
+    "###);
+    assert_eq!(interpreter.game().players[0].library.len(), 2);
+  }
+
+  #[test]
+  fn one_shot_replacement_is_spent() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let eff = &SkipNextDrawReplacement { player: 0 } as &dyn DrawReplacement;
+    g.replacement_effects
+      .insert("DRAW".to_string(), vec![serde_json::to_value(eff).unwrap()]);
+
+    let mut interpreter = Interpreter::new(&mut g);
+    let draw_result = interpreter.apply(draw_cards(2));
+
+    assert_yaml_snapshot!(draw_result, @r###"
+    ---
+    Ok:
+      - Skipped the draw
+      - Drew Island
+    "###);
+    assert!(interpreter.game().replacement_effects["DRAW"].is_empty());
+  }
+
+  #[test]
+  fn commander_tax_after_casting_twice() {
+    let mut g = GameBuilder::new()
+      .library(&["Forest"])
+      .command_zone(&["Isamaru, Hound of Konda"])
+      .build();
+
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(play_commander(CardId(2)));
+    assert_eq!(interpreter.game().command_zone, vec![CardId(2)]);
+
+    let cast_result = interpreter.apply(cast_from_command(CardId(2)));
+    assert_yaml_snapshot!(cast_result, @r###"Ok: "Cast Isamaru, Hound of Konda from the command zone""###);
+
+    // Sacrificing the commander returns it to the command zone, to cast again:
+    let sacrifice_result = interpreter.apply(sacrifice(CardId(2)));
+    assert_yaml_snapshot!(sacrifice_result, @r###"Ok: "Sacrificed Isamaru, Hound of Konda""###);
+    assert!(interpreter.game().players[0].graveyard.is_empty());
+
+    interpreter.apply(cast_from_command(CardId(2))).unwrap();
+    assert_eq!(interpreter.game().battlefield, vec![CardId(2)]);
+    assert_eq!(interpreter.game().commander_tax[&CardId(2)], 4);
+  }
+
+  #[test]
+  fn observer_sees_executed_and_replayed_effects() {
+    let events = Rc::new(RefCell::new(Vec::new()));
+
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let observed = events.clone();
+    let mut interpreter = Interpreter::new(&mut g)
+      .with_observer(move |event| observed.borrow_mut().push(event.clone()));
+
+    interpreter.apply(draw_cards(1)).unwrap();
+    let effects = interpreter.into_effects();
+
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let observed = events.clone();
+    let mut interpreter = Interpreter::from_effects(&mut g, effects)
+      .with_observer(move |event| observed.borrow_mut().push(event.clone()));
+
+    interpreter.apply(draw_cards(1)).unwrap();
+
+    // The nested draw finishes first, and the replay doesn't see it at all:
+    assert_yaml_snapshot!(*events.borrow(), @r###"
+    ---
+    - Executed:
+        label: ~
+        result:
+          Ok: Drew Island
+    - Executed:
+        label: ~
+        result:
+          Ok:
+            - Drew Island
+    - Replayed:
+        label: ~
+        result:
+          Ok:
+            - Drew Island
+    "###);
+  }
+
+  #[test]
+  fn sacrificed_token_vanishes() {
+    let mut g = game_with_library(&["Forest"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    let created = interpreter.apply(create_token("Soldier".to_string(), 3));
+    assert_eq!(created.len(), 3);
+    assert_eq!(
+      interpreter.game().battlefield,
+      vec![CardId(2), CardId(3), CardId(4)]
+    );
+
+    let sacrifice_result = interpreter.apply(sacrifice(CardId(3)));
+    assert_yaml_snapshot!(sacrifice_result, @r###"Ok: Sacrificed Soldier"###);
+
+    let game = interpreter.game();
+    assert_eq!(game.battlefield, vec![CardId(2), CardId(4)]);
+    assert!(game.players[0].graveyard.is_empty());
+    assert_eq!(game.name_of(CardId(3)), None);
+  }
+
+  #[test]
+  fn zero_life_loses_once() {
+    let mut g = game_with_library(&[]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(lose_life(20));
+    interpreter.apply(lose_life(3));
+
+    assert_eq!(interpreter.game().losers, vec![0]);
+    assert_yaml_snapshot!(interpreter.into_effects(), @r###"
+    ---
+    - result: Lost 20 life
+      children:
+        - label: state_based_action
+          result: Player 0 loses the game
+          children: []
+    - result: Lost 3 life
+      children: []
+    "###);
+  }
+
+  #[test]
+  fn position_hash_ignores_hand_order() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp", "Mountain"]);
+    Interpreter::new(&mut g).apply(draw_cards(2)).unwrap();
+    let hash = g.position_hash();
+
+    let mut reordered_hand = g.clone();
+    reordered_hand.players[0].hand.reverse();
+    assert_eq!(reordered_hand.position_hash(), hash);
+
+    let mut reordered_library = g.clone();
+    reordered_library.players[0].library.reverse();
+    assert_ne!(reordered_library.position_hash(), hash);
+
+    let round_trip: Game = serde_json::from_value(serde_json::to_value(&g).unwrap()).unwrap();
+    assert_eq!(round_trip.position_hash(), hash);
+
+    let mut animated_land = g.clone();
+    let forest = animated_land.cards.get_mut(&CardId(1)).unwrap();
+    forest.types = vec!["Land".to_string(), "Creature".to_string()];
+    assert_ne!(animated_land.position_hash(), hash);
+
+    let mut next_turn = g.clone();
+    next_turn.draws_this_turn = 0;
+    assert_ne!(next_turn.position_hash(), hash);
+
+    let mut mulliganed = g.clone();
+    mulliganed.mulligans.insert(0, 1);
+    assert_ne!(mulliganed.position_hash(), hash);
+  }
+
+  #[test]
+  fn mill_then_reshuffle_graveyard() {
+    let mut g = game_with_library(&["Plains", "Island", "Swamp", "Mountain", "Forest"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(mill(3)).unwrap();
+    assert_eq!(interpreter.game().players[0].library.len(), 2);
+
+    let reshuffled = interpreter.apply(graveyard_to_library);
+
+    assert_yaml_snapshot!(reshuffled, @r###"
+    ---
+    - Forest
+    - Mountain
+    - Swamp
+    "###);
+    assert!(interpreter.game().players[0].graveyard.is_empty());
+    assert_eq!(interpreter.game().players[0].library.len(), 5);
+  }
+
+  #[test]
+  fn restore_to_named_checkpoint() {
+    let mut g = game_with_library(&["Plains", "Island", "Swamp", "Mountain"]);
+    let mut interpreter = Interpreter::new(&mut g).with_rewind();
+
+    interpreter.apply(draw_card).unwrap();
+    interpreter.checkpoint("after-turn-one");
+    let after_turn_one = serde_json::to_value(interpreter.game()).unwrap();
+
+    interpreter.apply(draw_card).unwrap();
+    interpreter.apply(mill(1)).unwrap();
+    interpreter.checkpoint("after-turn-three");
+    assert_yaml_snapshot!(serde_json::to_value(&interpreter).unwrap()["named_checkpoints"], @r###"
+    ---
+    after-turn-one: 1
+    after-turn-three: 3
+    "###);
+
+    interpreter.restore("after-turn-one").unwrap();
+
+    assert_eq!(
+      serde_json::to_value(interpreter.game()).unwrap(),
+      after_turn_one
+    );
+    assert_eq!(interpreter.total_nodes(), 1);
+    assert!(interpreter.restore("after-turn-three").is_err());
+  }
+
+  #[test]
+  fn discard_one_of_three() {
+    let mut g = game_with_library(&["Plains", "Island", "Swamp"]);
+    let mut interpreter = Interpreter::new(&mut g).with_choice_callback(|request| {
+      assert_eq!(request.options, ["Swamp", "Island", "Plains"]);
+      1
+    });
+
+    interpreter.apply(draw_cards(3)).unwrap();
+    let discard_result = interpreter.apply(discard(1));
+
+    assert_yaml_snapshot!(discard_result, @r###"
+    ---
+    Ok:
+      - Discarded Island
+    "###);
+    assert_eq!(interpreter.game().players[0].graveyard, vec![CardId(2)]);
+    assert_eq!(
+      interpreter.apply(discard(3)),
+      Err(EffectError::NotEnoughCards {
+        zone: Zone::Hand,
+        count: 3
+      })
+    );
+  }
+
+  #[test]
+  fn pay_costs_from_mana_pool() {
+    let mut g = game_with_library(&[]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(add_mana('W', 2)).unwrap();
+    interpreter.apply(add_mana('C', 1)).unwrap();
+
+    // The generic mana is paid with the colorless mana, leaving a W:
+    assert_eq!(interpreter.apply(pay_cost("1W")), Ok(()));
+    assert_eq!(interpreter.game().mana_pool, HashMap::from([('W', 1)]));
+
+    assert_eq!(
+      interpreter.apply(pay_cost("2U")),
+      Err(EffectError::CannotPayCost {
+        cost: "2U".to_string()
+      })
+    );
+    assert_eq!(interpreter.game().mana_pool, HashMap::from([('W', 1)]));
+  }
+
+  #[test]
+  fn add_unknown_mana() {
+    let mut g = game_with_library(&[]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    assert_eq!(
+      interpreter.apply(add_mana('x', 3)),
+      Err(EffectError::UnknownManaSymbol { symbol: 'x' })
+    );
+    assert_eq!(
+      interpreter.apply(add_mana('w', 1)),
+      Err(EffectError::UnknownManaSymbol { symbol: 'w' })
+    );
+    assert!(interpreter.game().mana_pool.is_empty());
+  }
+
+  #[test]
+  fn remove_more_counters_than_there_are() {
+    let mut g = game_with_library(&["Grizzly Bears"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(draw_card).unwrap();
+    interpreter.apply(play_permanent(CardId(1))).unwrap();
+    interpreter.apply(add_counter(CardId(1), "+1/+1", 3));
+    assert_eq!(interpreter.game().counters[&CardId(1)]["+1/+1"], 3);
+
+    let removed = interpreter.apply(remove_counter(CardId(1), "+1/+1", 5));
+
+    assert_yaml_snapshot!(removed, @r###"Removed 3 +1/+1 counters from Grizzly Bears"###);
+    assert!(interpreter.game().counters.is_empty());
+  }
+
+  #[test]
+  fn add_zero_counters() {
+    let mut g = game_with_library(&["Grizzly Bears"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(draw_card).unwrap();
+    interpreter.apply(play_permanent(CardId(1))).unwrap();
+    let before = interpreter.game().clone();
+    interpreter.apply(add_counter(CardId(1), "+1/+1", 0));
+
+    assert!(interpreter.game().counters.is_empty());
+    assert_eq!(interpreter.game().position_hash(), before.position_hash());
+  }
+
+  #[test]
+  fn verify_replay_finds_nondeterminism() {
+    fn program(int: &mut Interpreter) {
+      int.apply(scry(2)).unwrap();
+      int.apply(shuffle);
+      int.apply(draw_card).unwrap();
+    }
+
+    let g = game_with_library(&["Plains", "Island", "Swamp"]);
+    let mut recording = g.clone();
+    let mut interpreter = Interpreter::new(&mut recording).with_choice_callback(|_| 1);
+    program(&mut interpreter);
+    let effects = interpreter.into_effects();
+
+    // Choices and randomness come from the interpreter, so this replays exactly:
+    assert_eq!(Interpreter::verify_replay(&g, &effects, 0, program), Ok(()));
+
+    thread_local! {
+      static NONCE: Cell<usize> = const { Cell::new(0) };
+    }
+    fn nondeterministic(int: &mut Interpreter) {
+      int.apply(draw_card).unwrap();
+      int.apply(|_| {
+        NONCE.set(NONCE.get() + 1);
+        NONCE.get()
+      });
+    }
+
+    let mut recording = g.clone();
+    let mut interpreter = Interpreter::new(&mut recording);
+    nondeterministic(&mut interpreter);
+    let effects = interpreter.into_effects();
+
+    let mismatch = Interpreter::verify_replay(&g, &effects, 0, nondeterministic).unwrap_err();
+    assert_snapshot!(mismatch, @r###"Replay diverged at [1]: recorded 1, but got 2"###);
+  }
+
+  #[test]
+  fn replacement_effects_serialize_in_key_order() {
+    let mut g = game_with_library(&["Forest"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(play_rest_in_peace);
+    interpreter.apply(replace_mill_with_exile);
+    interpreter.apply(prevent_all_damage);
+    interpreter.apply(replace_draw_with_discard);
+
+    assert_yaml_snapshot!(interpreter.game().replacement_effects, @r###"
+    ---
+    DAMAGE:
+      - prevent_all_damage: ~
+    DRAW:
+      - random_discard: ~
+    MILL:
+      - exile_milled_cards: ~
+    TO_GRAVEYARD:
+      - rest_in_peace: ~
+    "###);
+  }
+
+  #[test]
+  fn regrowth_a_milled_card() {
+    let mut g = game_with_library(&["Plains", "Island"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(mill(1)).unwrap();
+    let regrowth_result = interpreter.apply(regrowth("Island"));
+
+    assert_yaml_snapshot!(regrowth_result, @r###"Ok: Returned Island to hand"###);
+    assert_eq!(interpreter.game().players[0].hand, vec![CardId(2)]);
+    assert!(interpreter.game().players[0].graveyard.is_empty());
+
+    assert_eq!(
+      interpreter.apply(regrowth_card(CardId(2))),
+      Err(EffectError::CardNotFound {
+        zone: Zone::Graveyard,
+        card: "Island".to_string()
+      })
+    );
+  }
+
+  #[test]
+  fn scry_then_draw_nests_both_effects() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    let mut interpreter = Interpreter::new(&mut g).with_choice_callback(|_| 1);
+
+    let result = interpreter.apply(scry_then_draw(1));
+    assert_eq!(
+      result,
+      Ok(vec![
+        "Put Swamp on the bottom".to_string(),
+        "Drew Island".to_string()
+      ])
+    );
+
+    let effects = interpreter.into_effects();
+    assert_eq!(effects.len(), 1);
+    assert_eq!(effects[0].children.len(), 2);
+
+    let tree: String = effects.iter().map(EffectTree::render_tree).collect();
+    assert_snapshot!(tree, @r###"
+    {"Ok":["Put Swamp on the bottom","Drew Island"]}
+    ├─ {"Ok":["Put Swamp on the bottom"]}
+    │  └─ choose: 1
+    └─ {"Ok":"Drew Island"}
+    "###);
+  }
+
+  /// Replaces drawing a card with drawing a card, forever.
+  #[derive(Serialize, Deserialize)]
+  struct DrawInsteadReplacement;
+
+  impl ReplacementEffect for DrawInsteadReplacement {
+    type Event = usize;
+    type Value = Result<String, EffectError>;
+
+    fn apply(&self, int: &mut Interpreter, player: &usize) -> Self::Value {
+      int.apply_try(draw_card_for(*player))
+    }
+
+    fn check(&self, _: &Game, _: &usize) -> bool {
+      true
+    }
+  }
+
+  #[typetag::serde]
+  impl DrawReplacement for DrawInsteadReplacement {}
+
+  #[test]
+  fn self_replacing_draw_trips_depth_guard() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let eff = &DrawInsteadReplacement as &dyn DrawReplacement;
+    g.replacement_effects
+      .insert("DRAW".to_string(), vec![serde_json::to_value(eff).unwrap()]);
+
+    let mut interpreter = Interpreter::new(&mut g).with_max_depth(4);
+    let draw_result = interpreter.apply(draw_cards(1));
+
+    assert_eq!(
+      draw_result,
+      Err(EffectError::DepthExceeded { max_depth: 4 })
+    );
+    assert_eq!(interpreter.game().players[0].hand, vec![]);
+
+    let effects = interpreter.into_effects();
+    let tree: String = effects.iter().map(EffectTree::render_tree).collect();
+    assert_snapshot!(tree, @r###"
+    {"Err":{"DepthExceeded":{"max_depth":4}}}
+    └─ {"Err":{"DepthExceeded":{"max_depth":4}}}
+       └─ {"Err":{"DepthExceeded":{"max_depth":4}}}
+          └─ {"Err":{"DepthExceeded":{"max_depth":4}}}
+             └─ {"Err":{"DepthExceeded":{"max_depth":4}}}
+    "###);
+  }
+
+  #[test]
+  #[should_panic(
+    expected = "Effects nested more than 8 deep, applying an unlabeled effect at depth 8"
+  )]
+  fn depth_guard_stops_plain_apply() {
+    fn recurse(int: &mut Interpreter) {
+      int.apply(recurse);
+    }
+
+    let mut g = game_with_library(&[]);
+    let mut interpreter = Interpreter::new(&mut g).with_max_depth(8);
+    interpreter.apply(recurse);
+  }
+
+  #[test]
+  #[should_panic(expected = "Effects nested more than 3 deep, applying recurse at depth 3")]
+  fn depth_guard_names_labeled_effect() {
+    fn recurse(int: &mut Interpreter) {
+      int.apply_labeled("recurse", recurse);
+    }
+
+    let mut g = game_with_library(&[]);
+    let mut interpreter = Interpreter::new(&mut g).with_max_depth(3);
+    interpreter.apply_labeled("recurse", recurse);
+  }
+
+  #[test]
+  fn mill_and_reshuffle_conserve_cards() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp", "Mountain"]);
+    let mut interpreter = Interpreter::new(&mut g);
+    let before = interpreter.game().all_cards().count();
+
+    interpreter.apply(mill(2)).unwrap();
+    assert_eq!(
+      interpreter
+        .game()
+        .all_cards()
+        .filter(|&(_, zone)| zone == Zone::Graveyard)
+        .count(),
+      2
+    );
+
+    interpreter.apply(graveyard_to_library);
+    let mut cards = interpreter
+      .game()
+      .all_cards()
+      .map(|(card, _)| card)
+      .collect::<Vec<_>>();
+    assert_eq!(cards.len(), before);
+    cards.sort();
+    cards.dedup();
+    assert_eq!(cards.len(), before);
+  }
+
+  /// A buggy effect which puts a second copy of the top card into the hand.
+  fn duplicate_top_card(int: &mut Interpreter) {
+    let game = int.game_mut();
+    let card = *game.players[0].library.last().unwrap();
+    game.players[0].hand.push(card);
+  }
+
+  #[test]
+  fn conservation_catches_duplicated_card() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(create_token("Goblin".to_string(), 1));
+    interpreter.apply(draw_card).unwrap();
+    assert_eq!(interpreter.game().assert_conservation(2), Ok(()));
+
+    interpreter.apply(duplicate_top_card);
+    assert_eq!(
+      interpreter.game().assert_conservation(2),
+      Err("Expected 2 cards, but found 3".to_string())
+    );
+  }
+
+  #[test]
+  #[should_panic(expected = "Expected 2 cards, but found 3")]
+  fn conservation_check_panics_on_apply() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let mut interpreter = Interpreter::new(&mut g).with_conservation_check();
+    interpreter.apply(draw_card).unwrap();
+    interpreter.apply(duplicate_top_card);
+  }
+
+  #[test]
+  fn save_and_resume_finishes_the_game() {
+    fn turn_two(int: &mut Interpreter) {
+      int.apply(draw_card).unwrap();
+      int.apply(replace_draw_with_discard);
+    }
+
+    fn turn_three(int: &mut Interpreter) {
+      int.apply(draw_cards(1)).unwrap();
+    }
+
+    let mut g = game_with_library(&["Forest", "Island", "Swamp", "Mountain"]);
+    let mut uninterrupted = g.clone();
+
+    let mut interpreter = Interpreter::new(&mut g).with_rng_seed(7);
+    interpreter.apply(draw_card).unwrap();
+    interpreter.apply(turn_two);
+    let saved = serde_json::to_string(&interpreter.save()).unwrap();
+
+    let saved: SavedGame = serde_json::from_str(&saved).unwrap();
+    let mut resumed = saved.resume().unwrap();
+    resumed.apply(turn_three);
+    let resumed_game = serde_json::to_value(resumed.game()).unwrap();
+    assert_eq!(resumed.game().players[0].graveyard.len(), 1);
+    let resumed_effects = serde_json::to_value(resumed.into_effects()).unwrap();
+
+    let mut interpreter = Interpreter::new(&mut uninterrupted).with_rng_seed(7);
+    interpreter.apply(draw_card).unwrap();
+    interpreter.apply(turn_two);
+    interpreter.apply(turn_three);
+    let effects = serde_json::to_value(interpreter.into_effects()).unwrap();
+
+    assert_eq!(resumed_effects, effects);
+    assert_eq!(resumed_game, serde_json::to_value(&uninterrupted).unwrap());
+  }
+
+  #[test]
+  fn resume_rejects_a_corrupt_save() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(draw_card).unwrap();
+    interpreter.checkpoint("after-turn-one");
+
+    let mut saved = interpreter.save();
+    saved.position = 2;
+    assert_eq!(
+      saved.resume().err(),
+      Some("Saved at effect 2, but only 1 were applied".to_string())
+    );
+
+    let mut saved = interpreter.save();
+    saved.named_checkpoints.insert("later".to_string(), 3);
+    assert_eq!(
+      saved.resume().err(),
+      Some("Checkpoint later is ahead of the effects applied".to_string())
+    );
+
+    assert!(interpreter.save().resume().is_ok());
+  }
+
+  #[test]
+  fn impulse_then_play_exiled_card() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    let mut interpreter = Interpreter::new(&mut g).with_choice_callback(|_| 1);
+
+    let impulse_result = interpreter.apply(impulse(2));
+    assert_yaml_snapshot!(impulse_result, @r###"
+    ---
+    Ok:
+      - Exiled Swamp
+      - Exiled Island
+    "###);
+    assert_eq!(interpreter.game().exile, vec![CardId(3), CardId(2)]);
+
+    let play_result = interpreter.apply(play_exiled(CardId(2)));
+    assert_yaml_snapshot!(play_result, @r###"Ok: Played Island"###);
+    assert_eq!(interpreter.game().exile, vec![CardId(3)]);
+    assert_eq!(interpreter.game().battlefield, vec![CardId(2)]);
+
+    assert_eq!(
+      interpreter.apply(play_exiled(CardId(1))),
+      Err(EffectError::CardNotFound {
+        zone: Zone::Exile,
+        card: "Forest".to_string()
+      })
+    );
+
+    // Replaying the recorded choice plays the same card the same way:
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    let mut replay = Interpreter::from_effects(&mut g, interpreter.into_effects());
+    replay.apply(impulse(2)).unwrap();
+    assert_eq!(
+      replay.apply(play_exiled(CardId(2))),
+      Ok("Played Island".to_string())
+    );
+  }
+
+  #[cfg(feature = "profile")]
+  #[test]
+  fn profile_times_executed_effects_only() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(draw_cards(1)).unwrap();
+
+    let effects = interpreter.into_effects();
+    assert!(effects[0].micros().is_some());
+    assert!(effects[0].children[0].micros().is_some());
+
+    // Replaying doesn't run the effect, so it keeps the time it was recorded with:
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let mut replay = Interpreter::from_effects(&mut g, effects.clone());
+    replay.apply(draw_cards(1)).unwrap();
+    replay.apply(draw_cards(1)).unwrap();
+    let replayed = replay.into_effects();
+    assert_eq!(replayed[0].micros(), effects[0].micros());
+    assert!(replayed[1].micros().is_some());
+
+    // Times aren't saved, and don't make otherwise identical trees differ:
+    let imported = import_log(&serde_json::to_string(&effects).unwrap()).unwrap();
+    assert_eq!(imported[0].micros(), None);
+    assert_eq!(imported, effects);
+  }
+
+  #[test]
+  fn life_history_tracks_changes() {
+    let mut g = game_with_library(&[]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(gain_life(5)).unwrap();
+    interpreter.apply(lose_life(3));
+    assert_eq!(interpreter.game().life_history, vec![vec![20, 25, 22]]);
+    assert_eq!(interpreter.game().life_gained_this_turn(0), 5);
+
+    interpreter.game_mut().start_turn();
+    assert_eq!(interpreter.game().life_gained_this_turn(0), 0);
+    interpreter.apply(gain_life(2)).unwrap();
+    assert_eq!(interpreter.game().life_gained_this_turn(0), 2);
+  }
+
+  #[test]
+  fn draw_step_draws_a_card() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    let mut phases = Vec::new();
+    for _ in 0..7 {
+      phases.push(interpreter.advance_phase().unwrap());
+      if phases.last() == Some(&Phase::Draw) {
+        assert_eq!(interpreter.game().players[0].hand, vec![CardId(2)]);
+      }
+    }
+    assert_eq!(phases.last(), Some(&Phase::Untap));
+    assert_eq!(interpreter.game().players[0].hand, vec![CardId(2)]);
+
+    let effects = interpreter.into_effects();
+    let tree: String = effects.iter().map(EffectTree::render_tree).collect();
+    assert_snapshot!(tree, @r###"
+    advance_phase: {"Ok":"Upkeep"}
+    advance_phase: {"Ok":"Draw"}
+    └─ {"Ok":"Drew Island"}
+    advance_phase: {"Ok":"Main1"}
+    advance_phase: {"Ok":"Combat"}
+    advance_phase: {"Ok":"Main2"}
+    advance_phase: {"Ok":"End"}
+    advance_phase: {"Ok":"Untap"}
+    "###);
+  }
+
+  #[test]
+  fn draw_step_from_empty_library_stays_in_upkeep() {
+    let mut g = game_with_library(&[]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    assert_eq!(interpreter.advance_phase(), Ok(Phase::Upkeep));
+    assert_eq!(interpreter.advance_phase(), Err(EffectError::EmptyLibrary));
+    assert_eq!(interpreter.game().phase, Phase::Upkeep);
+
+    let effects = interpreter.into_effects();
+    let tree: String = effects.iter().map(EffectTree::render_tree).collect();
+    assert_snapshot!(tree, @r###"
+    advance_phase: {"Ok":"Upkeep"}
+    advance_phase: {"Err":"EmptyLibrary"}
+    └─ {"Err":"EmptyLibrary"}
+    "###);
+  }
+
+  #[test]
+  fn mulligan_once_keeps_six() {
+    let names = ["Plains", "Island", "Swamp", "Mountain", "Forest"];
+    let mut g = game_with_library(&names.repeat(2));
+    let mut interpreter = Interpreter::new(&mut g)
+      .with_rng_seed(3)
+      .with_choice_callback(|_| 0);
+    interpreter.apply(draw_cards(7)).unwrap();
+
+    let mulligan_result = interpreter.apply(mulligan).unwrap();
+    let game = interpreter.game();
+    assert_eq!(game.players[0].hand.len(), 6);
+    assert_eq!(game.players[0].library.len(), 4);
+    assert_eq!(game.mulligans[&0], 1);
+    assert_eq!(
+      mulligan_result.last(),
+      Some(&format!(
+        "Put {} on the bottom",
+        game.describe(game.players[0].library[0])
+      ))
+    );
+  }
+
+  #[test]
+  fn cloned_game_is_independent() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(draw_card).unwrap();
+    interpreter.apply(replace_draw_with_discard);
+    let original = interpreter.game();
+
+    let mut clone = original.clone();
+    let mut clone_interpreter = Interpreter::new(&mut clone);
+    clone_interpreter.apply(play_permanent(CardId(3))).unwrap();
+    clone_interpreter.apply(gain_life(3)).unwrap();
+    clone_interpreter.game_mut().replacement_effects.clear();
+
+    assert_eq!(original.players[0].hand, vec![CardId(3)]);
+    assert!(original.battlefield.is_empty());
+    assert_eq!(original.players[0].life, 20);
+    assert_eq!(original.replacement_effects["DRAW"].len(), 1);
+    assert_eq!(clone.battlefield, vec![CardId(3)]);
+    assert_eq!(clone.players[0].life, 23);
+  }
+
+  #[test]
+  fn applicable_replacements_for_draw() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(replace_draw_with_discard);
+
+    // There's nothing in hand to discard, so the draw isn't replaced yet:
+    assert_eq!(
+      interpreter.game().applicable_replacements("DRAW"),
+      Some(vec![])
+    );
+
+    interpreter.apply(draw_card).unwrap();
+    assert_eq!(
+      interpreter.game().applicable_replacements("DRAW"),
+      Some(vec!["random_discard".to_string()])
+    );
+    assert_eq!(
+      interpreter.game().applicable_replacements("MILL"),
+      Some(vec![])
+    );
+  }
+
+  #[test]
+  fn applicable_replacements_needs_the_event_for_damage() {
+    let mut g = game_with_library(&["Forest"]);
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(prevent_all_damage);
+
+    // Damage replacements are checked against the damage itself, which isn't
+    // known until it's dealt.
+    assert_eq!(interpreter.game().applicable_replacements("DAMAGE"), None);
+    assert_eq!(
+      interpreter.game().applicable_replacements("LIFE_GAIN"),
+      None
+    );
+  }
+
+  #[test]
+  fn may_draw_can_be_declined() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let mut interpreter = Interpreter::new(&mut g).with_choice_callback(|_| 1);
+
+    assert_eq!(interpreter.apply(may_draw), Ok(None));
+    assert_eq!(
+      interpreter.game().players[0].library,
+      vec![CardId(1), CardId(2)]
+    );
+    assert!(interpreter.game().players[0].hand.is_empty());
+
+    let effects = interpreter.into_effects();
+    let tree: String = effects.iter().map(EffectTree::render_tree).collect();
+    assert_snapshot!(tree, @r###"
+    {"Ok":null}
+    └─ choose: 1
+    "###);
+  }
+
+  #[test]
+  fn apply_all_nests_under_one_node() {
+    let mut g = game_with_library(&[]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    let effects: Vec<BoxedEffect<Result<String, EffectError>>> = vec![
+      Box::new(gain_life(1)),
+      Box::new(gain_life(2)),
+      Box::new(gain_life(3)),
+    ];
+    let results = interpreter.apply_all(effects);
+    assert_eq!(
+      results,
+      [
+        Ok("Added 1 life".to_string()),
+        Ok("Added 2 life".to_string()),
+        Ok("Added 3 life".to_string())
+      ]
+    );
+    assert_eq!(interpreter.game().players[0].life, 26);
+
+    let effects = interpreter.into_effects();
+    assert_eq!(effects.len(), 1);
+    assert_eq!(effects[0].children.len(), 3);
+
+    // Replaying returns the same results without gaining any life:
+    let mut g = game_with_library(&[]);
+    let mut replay = Interpreter::from_effects(&mut g, effects);
+    let replayed = replay.apply_all::<Result<String, EffectError>>(vec![
+      Box::new(gain_life(1)),
+      Box::new(gain_life(2)),
+      Box::new(gain_life(3)),
+    ]);
+    assert_eq!(replayed, results);
+    assert_eq!(replay.game().players[0].life, 20);
+  }
+
+  /// A trigger which does nothing but say which one it is.
+  #[derive(Serialize, Deserialize)]
+  struct AnnounceOnLifeGain {
+    controller: usize,
+    message: String,
+  }
+
+  #[typetag::serde]
+  impl TriggeredAbility for AnnounceOnLifeGain {
+    fn controller(&self) -> usize {
+      self.controller
+    }
+
+    fn check(&self, _: &Game, player: usize) -> bool {
+      player == self.controller
+    }
+
+    fn resolve(&self, _: &mut Interpreter) -> Result<String, EffectError> {
+      Ok(self.message.clone())
+    }
+  }
+
+  #[test]
+  fn same_player_triggers_resolve_in_registration_order() {
+    let mut g = game_with_library(&[]);
+    for message in ["First", "Second"] {
+      let ability = AnnounceOnLifeGain {
+        controller: 0,
+        message: message.to_string(),
+      };
+      g.triggered_abilities
+        .entry("GAIN_LIFE".to_string())
+        .or_default()
+        .push(serde_json::to_value(&ability as &dyn TriggeredAbility).unwrap());
+    }
+
+    // Without a choice callback, asking for an order would panic:
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(gain_life(1)).unwrap();
+
+    let effects = interpreter.into_effects();
+    let tree: String = effects.iter().map(EffectTree::render_tree).collect();
+    assert_snapshot!(tree, @r###"
+    {"Ok":"Added 1 life"}
+    ├─ trigger: {"Ok":"First"}
+    └─ trigger: {"Ok":"Second"}
+    "###);
+  }
+
+  #[test]
+  fn put_on_top_then_draw_it_back() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(draw_card).unwrap();
+
+    assert_eq!(
+      interpreter.apply(put_on_top(CardId(2))),
+      Ok("Put Island on top of library".to_string())
+    );
+    assert_eq!(
+      interpreter.game().players[0].library,
+      vec![CardId(1), CardId(2)]
+    );
+    assert_eq!(interpreter.apply(draw_card), Ok("Drew Island".to_string()));
+
+    assert_eq!(
+      interpreter.apply(put_on_top(CardId(1))),
+      Err(EffectError::CardNotFound {
+        zone: Zone::Hand,
+        card: "Forest".to_string()
+      })
+    );
+  }
+
+  #[test]
+  fn scripted_interface_resolves_replacements() {
+    let mut g = GameBuilder::new()
+      .library(&["Forest", "Island"])
+      .hand(&["Swamp"])
+      .build();
+    for eff in [
+      &RandomDiscardReplacement as &dyn DrawReplacement,
+      &SkipNextDrawReplacement { player: 0 },
+    ] {
+      g.replacement_effects
+        .entry("DRAW".to_string())
+        .or_default()
+        .push(serde_json::to_value(eff).unwrap());
+    }
+
+    // Skip the first draw, after which only the discard applies:
+    let mut interpreter = Interpreter::new(&mut g)
+      .with_rng_seed(5)
+      .with_interface(ScriptedInterface::new([1]));
+    let draw_result = interpreter.apply(draw_cards(2));
+
+    assert_yaml_snapshot!(draw_result, @r###"
+    ---
+    Ok:
+      - Skipped the draw
+      - Discarded Swamp
+    "###);
+    assert_eq!(interpreter.game().players[0].graveyard, vec![CardId(3)]);
+  }
+
+  #[test]
+  fn gyruda_puts_first_even_card_onto_battlefield() {
+    let mut g = game_with_library(&[
+      "Island",
+      "Grizzly Bears",
+      "Llanowar Elves",
+      "Hill Giant",
+      "Serra Angel",
+    ]);
+    for (card, mana_value) in [(1, 0), (2, 2), (3, 1), (4, 4), (5, 5)] {
+      let card = g.cards.get_mut(&CardId(card)).unwrap();
+      card.mana_value = mana_value;
+      card.types = vec![if mana_value == 0 { "Land" } else { "Creature" }.to_string()];
+    }
+
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(play_rest_in_peace);
+    let gyruda_result = interpreter.apply(gyruda);
+
+    assert_yaml_snapshot!(gyruda_result, @r###"
+    ---
+    Ok:
+      - Milled Serra Angel
+      - Milled Hill Giant
+      - Milled Llanowar Elves
+      - Milled Grizzly Bears
+      - Put Hill Giant onto the battlefield
+    "###);
+    assert_eq!(interpreter.game().battlefield, vec![CardId(4)]);
+    assert_eq!(
+      interpreter.game().exile,
+      vec![CardId(5), CardId(3), CardId(2)]
+    );
+  }
+
+  #[test]
+  fn filter_even_mana_value_creatures() {
+    let mut g = game_with_library(&[
+      "Forest",
+      "Sol Ring",
+      "Grizzly Bears",
+      "Hill Giant",
+      "Llanowar Elves",
+    ]);
+    for (card, mana_value, card_type) in [
+      (1, 0, "Land"),
+      (2, 1, "Artifact"),
+      (3, 2, "Creature"),
+      (4, 4, "Creature"),
+      (5, 1, "Creature"),
+    ] {
+      let card = g.cards.get_mut(&CardId(card)).unwrap();
+      card.mana_value = mana_value;
+      card.types = vec![card_type.to_string()];
+    }
+
+    let even_creatures = g.players[0]
+      .library
+      .iter()
+      .filter(|card| g.cards[card].is_creature() && g.cards[card].is_even_mv())
+      .map(|&card| g.describe(card))
+      .collect::<Vec<_>>();
+    assert_eq!(even_creatures, ["Grizzly Bears", "Hill Giant"]);
+
+    assert_json_snapshot!(g.cards[&CardId(4)], @r###"
+    ---
+    {
+      "name": "Hill Giant",
+      "owner": 0,
+      "mana_value": 4,
+      "types": [
+        "Creature"
+      ]
+    }
+    "###);
+  }
+
+  #[test]
+  fn reveal_hand_records_without_changing_it() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(draw_cards(2)).unwrap();
+
+    assert_eq!(interpreter.apply(reveal_hand), ["Swamp", "Island"]);
+    assert_eq!(
+      interpreter.game().players[0].hand,
+      vec![CardId(3), CardId(2)]
+    );
+
+    // The replay returns the recorded reveal, whatever is in hand now:
+    let effects = interpreter.into_effects();
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    let mut replay = Interpreter::from_effects(&mut g, effects);
+    replay.apply(draw_cards(2)).unwrap();
+    replay.game_mut().players[0].hand.clear();
+    assert_eq!(replay.apply(reveal_hand), ["Swamp", "Island"]);
+  }
+
+  #[test]
+  fn skip_next_draw_then_draw_normally() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(skip_next_draw);
+
+    assert_eq!(
+      interpreter.apply(draw_card),
+      Ok("Skipped the draw".to_string())
+    );
+    assert_eq!(interpreter.game().players[0].library.len(), 2);
+    assert_eq!(interpreter.apply(draw_card), Ok("Drew Island".to_string()));
+    assert!(interpreter.game().replacement_effects["DRAW"].is_empty());
+  }
+
+  #[test]
+  fn draw_opening_hand_of_seven() {
+    let names = ["Plains", "Island", "Swamp", "Mountain", "Forest"];
+    let mut g = game_with_library(&names.repeat(2));
+    let mut interpreter = Interpreter::new(&mut g).with_rng_seed(11);
+
+    let drawn = interpreter.apply(draw_opening_hand).unwrap();
+    assert_eq!(drawn.len(), 7);
+    assert_eq!(interpreter.game().players[0].hand.len(), 7);
+    assert_eq!(interpreter.game().players[0].library.len(), 3);
+  }
+
+  #[test]
+  fn strict_replacements_report_malformed_effects() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    g.replacement_effects.insert(
+      "DRAW".to_string(),
+      vec![serde_json::json!({ "NoSuchReplacement": null })],
+    );
+
+    // By default the malformed effect is ignored:
+    let mut lenient = g.clone();
+    let mut interpreter = Interpreter::new(&mut lenient);
+    assert_eq!(interpreter.apply(draw_card), Ok("Drew Island".to_string()));
+
+    let mut interpreter = Interpreter::new(&mut g).with_strict_replacements();
+    let draw_result = interpreter.apply(draw_card);
+    assert!(matches!(
+      draw_result,
+      Err(EffectError::MalformedReplacement { key, name, .. })
+        if key == "DRAW" && name == "NoSuchReplacement"
+    ));
+    assert_eq!(interpreter.game().players[0].library.len(), 2);
+  }
+
+  #[test]
+  fn gain_life_for_another_player() {
+    let mut g = game_with_library(&[]);
+    g.players.push(g.players[0].clone());
+    let mut interpreter = Interpreter::new(&mut g);
+
+    assert_eq!(
+      interpreter.apply(gain_life_for(1, 4)),
+      Ok("Added 4 life".to_string())
+    );
+    assert_eq!(interpreter.game().players[0].life, 20);
+    assert_eq!(interpreter.game().players[1].life, 24);
+  }
+
+  #[test]
+  fn double_life_gain() {
+    let mut g = game_with_library(&[]);
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(play_double_life);
+
+    assert_eq!(
+      interpreter.apply(gain_life(5)),
+      Ok("Added 10 life".to_string())
+    );
+    assert_eq!(interpreter.game().players[0].life, 30);
+  }
+
+  #[test]
+  fn discard_asks_for_a_card_from_hand() {
+    let mut g = game_with_library(&["Plains", "Island"]);
+    let requests = Rc::new(RefCell::new(Vec::new()));
+    let recorded = requests.clone();
+    let mut interpreter = Interpreter::new(&mut g).with_choice_callback(move |request| {
+      recorded.borrow_mut().push(request.clone());
+      0
+    });
+
+    interpreter.apply(draw_cards(2)).unwrap();
+    interpreter.apply(discard(1)).unwrap();
+
+    assert_eq!(
+      *requests.borrow(),
+      [ChoiceRequest {
+        prompt: "Discard a card".to_string(),
+        options: vec!["Island".to_string(), "Plains".to_string()],
+        min: 1,
+        max: 1,
+      }]
+    );
+  }
+
+  #[test]
+  fn bounce_permanents() {
+    let mut g = game_with_library(&["Grizzly Bears"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(draw_card).unwrap();
+    interpreter.apply(play_permanent(CardId(1))).unwrap();
+    interpreter.apply(create_token("Soldier".to_string(), 1));
+
+    assert_eq!(
+      interpreter.apply(bounce(CardId(1))),
+      Ok("Returned Grizzly Bears to hand".to_string())
+    );
+    assert_eq!(
+      interpreter.apply(bounce(CardId(2))),
+      Ok("Returned Soldier to hand".to_string())
+    );
+    assert_eq!(
+      interpreter.apply(bounce(CardId(2))),
+      Err(EffectError::CardNotFound {
+        zone: Zone::Battlefield,
+        card: "card #2".to_string()
+      })
+    );
+
+    let game = interpreter.game();
+    assert!(game.battlefield.is_empty());
+    assert_eq!(game.players[0].hand, vec![CardId(1)]);
+    assert_eq!(game.name_of(CardId(2)), None);
+  }
+
+  #[test]
+  fn game_serializes_byte_stable() {
+    // Each game gets its own randomly seeded HashMaps, so their iteration orders
+    // differ between the two.
+    fn serialize_game() -> String {
+      let mut g = game_with_library(&["Plains", "Island"]);
+      let mut interpreter = Interpreter::new(&mut g);
+      interpreter.apply(play_rest_in_peace);
+      interpreter.apply(prevent_all_damage);
+      interpreter.apply(play_double_life);
+      interpreter.apply(draw_on_life_gain);
+      let game = interpreter.game_mut();
+      for key in ["UPKEEP", "DRAW", "END_STEP", "ATTACK", "CAST"] {
+        game.triggered_abilities.insert(key.to_string(), Vec::new());
+      }
+      for symbol in "WUBRGC".chars() {
+        game.mana_pool.insert(symbol, 1);
+      }
+      serde_json::to_string(&g).unwrap()
+    }
+
+    let first = serialize_game();
+    for _ in 0..8 {
+      assert_eq!(serialize_game(), first);
+    }
+  }
+
+  #[test]
+  fn impulse_then_cast_from_exile() {
+    let mut g = game_with_library(&["Forest", "Brazen Borrower", "Swamp"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(impulse(2)).unwrap();
+    assert_eq!(interpreter.game().exile, vec![CardId(3), CardId(2)]);
+
+    assert_eq!(
+      interpreter.apply(cast_from_exile(CardId(2))),
+      Ok("Cast Brazen Borrower from exile".to_string())
+    );
+    let game = interpreter.game();
+    assert_eq!(game.exile, vec![CardId(3)]);
+    assert_eq!(game.battlefield, vec![CardId(2)]);
+    assert_eq!(game.players[0].library, vec![CardId(1)]);
+
+    assert_eq!(
+      interpreter.apply(cast_from_exile(CardId(1))),
+      Err(EffectError::CardNotFound {
+        zone: Zone::Exile,
+        card: "Forest".to_string()
+      })
+    );
+  }
+
+  #[test]
+  fn draw_from_empty_library_loses_as_state_based_action() {
+    let mut g = game_with_library(&["Forest"]);
+    let mut interpreter = Interpreter::new(&mut g).with_lose_on_empty_draw();
+
+    interpreter.apply(|int| {
+      assert_eq!(
+        int.apply(draw_cards(2)),
+        Ok(vec![
+          "Drew Forest".to_string(),
+          "Drew from an empty library".to_string()
+        ])
+      );
+      // The loss waits for state-based actions, after the top level effect:
+      assert_eq!(int.game().empty_draws, vec![0]);
+      assert!(int.game().losers.is_empty());
+    });
+
+    let game = interpreter.game();
+    assert!(game.empty_draws.is_empty());
+    assert_eq!(game.losers, vec![0]);
+  }
+
+  #[test]
+  fn look_then_draws_moxen_and_bottoms_the_rest() {
+    let mut g = game_with_library(&["Forest", "Mox Jet", "Island", "Mox Pearl"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    let look_result = interpreter.apply(look_then(3, |int, card| {
+      let name = int.game().describe(card);
+      if name.starts_with("Mox") {
+        return draw_card(int);
+      }
+      int.game_mut().bottom_card(card);
+      Ok(format!("Put {name} on the bottom"))
+    }));
+
+    assert_eq!(
+      look_result,
+      Ok(vec![
+        "Drew Mox Pearl".to_string(),
+        "Put Island on the bottom".to_string(),
+        "Drew Mox Jet".to_string()
+      ])
+    );
+    let game = interpreter.game();
+    assert_eq!(game.players[0].hand, vec![CardId(4), CardId(2)]);
+    assert_eq!(game.players[0].library, vec![CardId(3), CardId(1)]);
+    let tree = interpreter.into_effects()[0].render_tree();
+    assert_snapshot!(tree, @r###"
+    {"Ok":["Drew Mox Pearl","Put Island on the bottom","Drew Mox Jet"]}
+    ├─ look: ["Mox Pearl","Island","Mox Jet"]
+    ├─ {"Ok":"Drew Mox Pearl"}
+    ├─ {"Ok":"Put Island on the bottom"}
+    └─ {"Ok":"Drew Mox Jet"}
+    "###);
+  }
+
+  #[test]
+  fn legacy_effect_tags_still_load() {
+    let mut g = game_with_library(&["Forest", "Island"]);
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(play_rest_in_peace);
+    interpreter.apply(draw_on_life_gain);
+    let saved = serde_json::to_value(&g).unwrap();
+    assert_eq!(
+      saved["replacement_effects"]["TO_GRAVEYARD"],
+      serde_json::json!([{ "rest_in_peace": null }])
+    );
+
+    // A game saved when effects were tagged with their type names:
+    let mut legacy = saved.clone();
+    legacy["replacement_effects"]["TO_GRAVEYARD"] = serde_json::json!([{ "RestInPeace": null }]);
+    legacy["triggered_abilities"]["GAIN_LIFE"] =
+      serde_json::json!([{ "DrawOnLifeGain": { "controller": 0 } }]);
+
+    let mut loaded: Game = serde_json::from_value(legacy).unwrap();
+    assert_eq!(serde_json::to_value(&loaded).unwrap(), saved);
+
+    let mut interpreter = Interpreter::new(&mut loaded);
+    interpreter.apply(gain_life(1)).unwrap();
+    interpreter.apply(mill_cards(1)).unwrap();
+    assert_eq!(interpreter.game().exile, vec![CardId(1)]);
+  }
+
+  #[test]
+  fn execution_count_skips_replays() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(draw_cards(3)).unwrap();
+    interpreter.apply(gain_life(2)).unwrap();
+    interpreter.apply_labeled("shuffle", shuffle);
+
+    assert_eq!(interpreter.execution_count("draw_card"), 3);
+    assert_eq!(interpreter.execution_count("gain_life"), 1);
+    assert_eq!(interpreter.execution_count("shuffle"), 1);
+    assert_eq!(interpreter.execution_count("scry"), 0);
+
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    let mut replay = Interpreter::from_effects(&mut g, interpreter.into_effects());
+    replay.apply(draw_cards(3)).unwrap();
+    replay.apply(gain_life(2)).unwrap();
+    replay.apply_labeled("shuffle", shuffle);
+    assert_eq!(replay.execution_count("draw_card"), 0);
+    assert_eq!(replay.execution_count("gain_life"), 0);
+    assert_eq!(replay.execution_count("shuffle"), 0);
+  }
+
+  #[test]
+  fn scripted_scry_bottoms_then_keeps() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    let (bottom, keep) = (1, 0);
+    let mut interpreter =
+      Interpreter::new(&mut g).with_interface(ScriptedInterface::new([bottom, keep]));
+
+    assert_eq!(
+      interpreter.apply(scry(2)),
+      Ok(vec![
+        "Put Swamp on the bottom".to_string(),
+        "Kept Island on top".to_string()
+      ])
+    );
+    assert_eq!(
+      interpreter.game().players[0].library,
+      vec![CardId(3), CardId(1), CardId(2)]
+    );
+  }
+
+  #[test]
+  fn replay_subtree_reproduces_a_turn() {
+    fn turn(int: &mut Interpreter) -> Result<Vec<String>, EffectError> {
+      let mut results = int.apply_try(draw_cards(3))?;
+      results.extend(int.apply_try(discard(1))?);
+      results.push(int.apply_try(gain_life(5))?);
+      int.apply(shuffle);
+      Ok(results)
+    }
+
+    let names = ["Forest", "Island", "Swamp", "Mountain", "Plains"];
+    let mut before = game_with_library(&names);
+    let mut g = before.clone();
+    let mut interpreter = Interpreter::new(&mut g)
+      .with_rng_seed(7)
+      .with_choice_callback(|_| 1);
+    let turn_result = interpreter.apply(turn).unwrap();
+    let effects = interpreter.into_effects();
+
+    let mut replay = Interpreter::from_effects(&mut before, effects);
+    assert_eq!(replay.replay_subtree(&[0], turn), Ok(Ok(turn_result)));
+    assert_eq!(
+      replay.replay_subtree(&[0, 5], turn).unwrap_err(),
+      "No effect at [0, 5]"
+    );
+    assert_eq!(
+      serde_json::to_value(&before).unwrap(),
+      serde_json::to_value(&g).unwrap()
+    );
+    assert_eq!(before.players[0].life, 25);
+    assert_eq!(before.players[0].graveyard, vec![CardId(4)]);
+  }
+
+  #[test]
+  fn lifelink_damage_gains_life() {
+    let mut g = game_with_library(&[]);
+    g.players.push(g.players[0].clone());
+    let mut interpreter = Interpreter::new(&mut g);
+    let source = DamageSource {
+      controller: 1,
+      lifelink: true,
+    };
+
+    interpreter
+      .apply(deal_damage_from(Some(source), 4))
+      .unwrap();
+    assert_eq!(interpreter.game().players[0].life, 16);
+    assert_eq!(interpreter.game().players[1].life, 24);
+
+    // The life gained is replaceable too, and doubling it doesn't loop:
+    interpreter.game_mut().active_player = 1;
+    interpreter.apply(play_double_life);
+    interpreter.game_mut().active_player = 0;
+    interpreter
+      .apply(deal_damage_from(Some(source), 4))
+      .unwrap();
+    assert_eq!(interpreter.game().players[0].life, 12);
+    assert_eq!(interpreter.game().players[1].life, 32);
+
+    let tree = interpreter.into_effects()[2].render_tree();
+    assert_snapshot!(tree, @r###"
+    {"Ok":"Dealt 4 damage to player 0"}
+    └─ trigger: {"Ok":"Added 8 life"}
+    "###);
+  }
+
+  #[test]
+  fn discard_whole_hand() {
+    let mut g = game_with_library(&["Plains", "Island", "Swamp", "Forest"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(draw_cards(3)).unwrap();
+    assert_eq!(
+      interpreter.apply(discard_hand),
+      Ok(vec![
+        "Discarded Forest".to_string(),
+        "Discarded Swamp".to_string(),
+        "Discarded Island".to_string()
+      ])
+    );
+    let game = interpreter.game();
+    assert!(game.players[0].hand.is_empty());
+    assert_eq!(
+      game.players[0].graveyard,
+      vec![CardId(4), CardId(3), CardId(2)]
+    );
+
+    // With Rest in Peace, the discarded cards are exiled instead:
+    interpreter.apply(play_rest_in_peace);
+    interpreter.apply(draw_card).unwrap();
+    interpreter.apply(discard_hand).unwrap();
+    assert_eq!(interpreter.game().exile, vec![CardId(1)]);
+    assert_eq!(interpreter.game().players[0].graveyard.len(), 3);
+  }
+
+  #[test]
+  fn wheel_discards_then_draws_seven() {
+    let names = (1..=12).map(|n| format!("Card {n}")).collect::<Vec<_>>();
+    let mut g = game_with_library(&names.iter().map(String::as_str).collect::<Vec<_>>());
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(draw_cards(2)).unwrap();
+    let wheel_result = interpreter.apply(wheel).unwrap();
+    assert_eq!(wheel_result.len(), 9);
+
+    let game = interpreter.game();
+    assert_eq!(game.players[0].hand.len(), 7);
+    assert_eq!(game.players[0].graveyard, vec![CardId(12), CardId(11)]);
+    assert_eq!(game.players[0].library.len(), 3);
+
+    // The discards and draws are the two children of the wheel:
+    let effects = interpreter.into_effects();
+    let children = &effects[1].children;
+    assert_eq!(children.len(), 2);
+    assert_eq!(children[1].children.len(), 7);
+  }
+
+  #[test]
+  fn move_between_zones() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(draw_card).unwrap();
+    interpreter.apply(play_permanent(CardId(3))).unwrap();
+    assert_eq!(
+      interpreter.apply(move_between(CardId(3), Zone::Battlefield, Zone::Library)),
+      Ok("Swamp".to_string())
+    );
+    assert!(interpreter.game().battlefield.is_empty());
+    assert_eq!(
+      interpreter.game().players[0].library,
+      vec![CardId(1), CardId(2), CardId(3)]
+    );
+
+    // Moving to the graveyard is replaceable:
+    interpreter.apply(play_rest_in_peace);
+    interpreter
+      .apply(move_between(CardId(3), Zone::Library, Zone::Graveyard))
+      .unwrap();
+    assert_eq!(interpreter.game().exile, vec![CardId(3)]);
+
+    assert_eq!(
+      interpreter.apply(move_between(CardId(3), Zone::Hand, Zone::Battlefield)),
+      Err(EffectError::CardNotFound {
+        zone: Zone::Hand,
+        card: "Swamp".to_string()
+      })
+    );
+  }
+
+  #[test]
+  fn board_wipe_moves_cards_together() {
+    let mut g = game_with_library(&["Grizzly Bears", "Llanowar Elves", "Serra Angel"]);
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(draw_cards(3)).unwrap();
+    for card in [CardId(1), CardId(2), CardId(3)] {
+      interpreter.apply(play_permanent(card)).unwrap();
+    }
+
+    let battlefield = interpreter.game().battlefield.clone();
+    assert_eq!(
+      interpreter.apply(move_cards(
+        vec![CardId(1), CardId(4)],
+        Zone::Battlefield,
+        Zone::Graveyard
+      )),
+      Err(EffectError::CardNotFound {
+        zone: Zone::Battlefield,
+        card: "card #4".to_string()
+      })
+    );
+    assert_eq!(interpreter.game().battlefield, battlefield);
+
+    let wiped = interpreter.apply(move_cards(battlefield, Zone::Battlefield, Zone::Graveyard));
+    assert_eq!(wiped, Ok(vec![CardId(1), CardId(2), CardId(3)]));
+    let game = interpreter.game();
+    assert!(game.battlefield.is_empty());
+    assert_eq!(
+      game.players[0].graveyard,
+      vec![CardId(1), CardId(2), CardId(3)]
+    );
+
+    // The whole wipe is a single effect:
+    let effects = interpreter.into_effects();
+    assert_yaml_snapshot!(effects.last().unwrap(), @r###"
+    ---
+    result:
+      Ok:
+        - 1
+        - 2
+        - 3
+    children: []
     "###);
   }
+
+  #[test]
+  fn pending_choice_waits_for_an_answer() {
+    let mut g = game_with_library(&["Plains", "Island"]);
+    let mut interpreter = Interpreter::new(&mut g).with_rewind();
+    interpreter.apply(draw_cards(2)).unwrap();
+    assert_eq!(interpreter.pending_choice(), None);
+
+    assert_eq!(
+      interpreter.apply(discard(1)),
+      Err(EffectError::ChoicePending)
+    );
+    assert_eq!(
+      interpreter.pending_choice(),
+      Some(ChoiceRequest {
+        prompt: "Discard a card".to_string(),
+        options: vec!["Island".to_string(), "Plains".to_string()],
+        min: 1,
+        max: 1,
+      })
+    );
+    assert_eq!(interpreter.game().players[0].hand.len(), 2);
+
+    // Once the answer is known, rewind the effect and apply it again:
+    interpreter.rewind(1).unwrap();
+    let effects = interpreter.into_effects();
+    let mut interpreter =
+      Interpreter::from_effects(&mut g, effects).with_interface(ScriptedInterface::new([1]));
+    interpreter.apply(draw_cards(2)).unwrap();
+    assert_eq!(
+      interpreter.apply(discard(1)),
+      Ok(vec!["Discarded Plains".to_string()])
+    );
+    assert_eq!(interpreter.pending_choice(), None);
+  }
+
+  #[test]
+  fn step_through_a_scry() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    let mut stepper = StepInterpreter::new(&mut g, |int| scry(2)(int));
+
+    let Step::NeedChoice(request) = stepper.step() else {
+      panic!("Expected the scry to need a choice");
+    };
+    assert_eq!(request.prompt, "Scry Swamp");
+    assert_eq!(request.options, ["Top", "Bottom"]);
+
+    let Step::NeedChoice(request) = stepper.provide_choice(1) else {
+      panic!("Expected the scry to need a second choice");
+    };
+    assert_eq!(request.prompt, "Scry Island");
+
+    assert_eq!(
+      stepper.provide_choice(0),
+      Step::Done(Ok(vec![
+        "Put Swamp on the bottom".to_string(),
+        "Kept Island on top".to_string()
+      ]))
+    );
+    let effects = stepper.into_effects();
+    let choices = effects[0].find_by_label("choose");
+    assert_eq!(choices.len(), 2);
+    assert_eq!(g.players[0].library, vec![CardId(3), CardId(1), CardId(2)]);
+  }
+
+  #[test]
+  fn count_cards_in_zones() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp", "Mountain", "Plains"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(draw_cards(3)).unwrap();
+    interpreter.apply(play_permanent(CardId(4))).unwrap();
+    interpreter.apply(mill(1)).unwrap();
+    interpreter.apply(impulse(1)).unwrap();
+
+    let game = interpreter.game();
+    assert_eq!(game.zone_len(Zone::Library), 0);
+    assert_eq!(game.hand_size(), 2);
+    assert_eq!(game.zone_len(Zone::Hand), 2);
+    assert_eq!(game.zone_len(Zone::Battlefield), 1);
+    assert_eq!(game.zone_len(Zone::Graveyard), 1);
+    assert_eq!(game.zone_len(Zone::Exile), 1);
+    assert_eq!(game.zone_len(Zone::Command), 0);
+  }
+
+  #[test]
+  fn cleanup_discards_down_to_seven() {
+    let names = (1..=9).map(|n| format!("Card {n}")).collect::<Vec<_>>();
+    let mut g = game_with_library(&names.iter().map(String::as_str).collect::<Vec<_>>());
+    let mut interpreter = Interpreter::new(&mut g).with_choice_callback(|_| 0);
+
+    interpreter.apply(draw_cards(9)).unwrap();
+    assert_eq!(
+      interpreter.apply(cleanup),
+      Ok(vec![
+        "Discarded Card 9".to_string(),
+        "Discarded Card 8".to_string()
+      ])
+    );
+    assert_eq!(interpreter.game().hand_size(), 7);
+    assert_eq!(interpreter.game().zone_len(Zone::Graveyard), 2);
+    assert_eq!(interpreter.apply(cleanup), Ok(Vec::new()));
+
+    let effects = interpreter.into_effects();
+    assert_eq!(effects[1].find_by_label("choose").len(), 2);
+  }
+
+  #[test]
+  fn bottomed_card_is_drawn_last() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    g.bottom_card(CardId(3));
+    assert_eq!(g.players[0].library, vec![CardId(3), CardId(1), CardId(2)]);
+
+    let mut interpreter = Interpreter::new(&mut g);
+    assert_eq!(
+      interpreter.apply(draw_cards(3)),
+      Ok(vec![
+        "Drew Island".to_string(),
+        "Drew Forest".to_string(),
+        "Drew Swamp".to_string()
+      ])
+    );
+  }
+
+  #[test]
+  fn top_of_library_is_drawn_next() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    g.push_on_top(CardId(1));
+    assert_eq!(g.players[0].library, vec![CardId(2), CardId(3), CardId(1)]);
+
+    let mut interpreter = Interpreter::new(&mut g);
+    while let Some(&top) = interpreter.game().top_of_library(0) {
+      let name = interpreter.game().describe(top);
+      assert_eq!(interpreter.apply(draw_card), Ok(format!("Drew {name}")));
+      assert_eq!(interpreter.game().players[0].hand.last(), Some(&top));
+    }
+    assert_eq!(interpreter.game().hand_size(), 3);
+  }
+
+  #[test]
+  fn shuffle_card_from_hand_into_library() {
+    fn program(int: &mut Interpreter) {
+      int.apply(draw_card).unwrap();
+      int.apply(shuffle_into_library(CardId(5))).unwrap();
+    }
+
+    let start = game_with_library(&["Forest", "Island", "Swamp", "Mountain", "Plains"]);
+    let mut g = start.clone();
+    let mut interpreter = Interpreter::new(&mut g).with_rng_seed(3);
+    program(&mut interpreter);
+    assert_eq!(
+      interpreter.apply(shuffle_into_library(CardId(5))),
+      Err(EffectError::CardNotFound {
+        zone: Zone::Hand,
+        card: "Plains".to_string()
+      })
+    );
+
+    let library = interpreter.game().players[0].library.clone();
+    assert_eq!(library.len(), 5);
+    assert!(interpreter.game().players[0].hand.is_empty());
+    let effects = interpreter.into_effects();
+    assert_eq!(
+      Interpreter::verify_replay(&start, &effects[..2], 3, program),
+      Ok(())
+    );
+
+    // Running it again with the same seed shuffles the same way:
+    let mut again = start.clone();
+    program(&mut Interpreter::new(&mut again).with_rng_seed(3));
+    assert_eq!(again.players[0].library, library);
+  }
+
+  #[test]
+  fn count_draws_this_turn() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(draw_card).unwrap();
+    interpreter.apply(draw_card).unwrap();
+    assert_eq!(interpreter.game().draws_this_turn(), 2);
+
+    // The draw step draws a third card, then the next turn starts afresh:
+    for _ in 0..6 {
+      interpreter.advance_phase().unwrap();
+    }
+    assert_eq!(interpreter.game().phase, Phase::End);
+    assert_eq!(interpreter.game().draws_this_turn(), 3);
+    assert_eq!(interpreter.advance_phase(), Ok(Phase::Untap));
+    assert_eq!(interpreter.game().draws_this_turn(), 0);
+  }
+
+  #[test]
+  fn sylvan_library_draws_extra_once_per_turn() {
+    let names = (1..=8).map(|n| format!("Card {n}")).collect::<Vec<_>>();
+    let mut g = game_with_library(&names.iter().map(String::as_str).collect::<Vec<_>>());
+    let mut interpreter = Interpreter::new(&mut g);
+    interpreter.apply(play_sylvan_library);
+
+    assert_eq!(
+      interpreter.apply(draw_card),
+      Ok("Drew Card 8, Drew Card 7, Drew Card 6".to_string())
+    );
+    assert_eq!(interpreter.apply(draw_card), Ok("Drew Card 5".to_string()));
+    assert_eq!(interpreter.game().hand_size(), 4);
+
+    // The next turn, the first draw gets the extra cards again:
+    interpreter.game_mut().start_turn();
+    assert_eq!(
+      interpreter.apply(draw_card),
+      Ok("Drew Card 4, Drew Card 3, Drew Card 2".to_string())
+    );
+    assert_eq!(interpreter.game().hand_size(), 7);
+    assert_eq!(interpreter.game().draws_this_turn(), 3);
+  }
+
+  #[test]
+  fn sylvan_library_with_an_empty_library() {
+    let mut g = game_with_library(&[]);
+    let mut interpreter = Interpreter::new(&mut g).with_lose_on_empty_draw();
+    interpreter.apply(play_sylvan_library);
+
+    // The empty draw is still the first, so the extra draws aren't replaced again:
+    assert_eq!(
+      interpreter.apply(draw_card),
+      Ok(["Drew from an empty library"; 3].join(", "))
+    );
+    assert_eq!(interpreter.game().draws_this_turn(), 3);
+    assert_eq!(interpreter.game().losers, vec![0]);
+  }
+
+  #[test]
+  fn game_builder_defaults() {
+    let g = GameBuilder::new().library(&["Forest", "Island"]).build();
+
+    assert_eq!(g.players.len(), 1);
+    assert_eq!(g.players[0].life, 20);
+    assert_eq!(g.players[0].library, vec![CardId(1), CardId(2)]);
+    assert!(g.players[0].hand.is_empty());
+    assert!(g.players[0].graveyard.is_empty());
+    assert_eq!(g.active_player, 0);
+    assert_eq!(g.phase, Phase::Untap);
+    assert!(g.battlefield.is_empty());
+    assert!(g.replacement_effects.is_empty());
+    assert_eq!(g.name_of(CardId(2)), Some("Island"));
+    assert_eq!(g.owner_of(CardId(2)), 0);
+
+    let g = GameBuilder::new().hand(&["Mox Pearl"]).life(7).build();
+    assert_eq!(g.players[0].hand, vec![CardId(1)]);
+    assert_eq!(g.players[0].life, 7);
+  }
+
+  #[test]
+  fn saved_game_keeps_checkpoints() {
+    let mut g = game_with_library(&["Plains", "Island", "Swamp", "Mountain"]);
+    let mut interpreter = Interpreter::new(&mut g).with_rewind();
+
+    interpreter.apply(draw_card).unwrap();
+    interpreter.checkpoint("after-turn-one");
+    let after_turn_one = serde_json::to_value(interpreter.game()).unwrap();
+    interpreter.apply(draw_card).unwrap();
+    let after_turn_two = serde_json::to_value(interpreter.game()).unwrap();
+    let saved = serde_json::to_string(&interpreter.save()).unwrap();
+
+    let saved: SavedGame = serde_json::from_str(&saved).unwrap();
+    let mut resumed = saved.resume().unwrap();
+    resumed.apply(mill(1)).unwrap();
+
+    // Rewinding goes back past the point the game was saved at:
+    resumed.rewind(2).unwrap();
+    assert_eq!(
+      serde_json::to_value(resumed.game()).unwrap(),
+      after_turn_one
+    );
+
+    resumed.apply(draw_card).unwrap();
+    assert_eq!(
+      serde_json::to_value(resumed.game()).unwrap(),
+      after_turn_two
+    );
+    resumed.restore("after-turn-one").unwrap();
+    assert_eq!(
+      serde_json::to_value(resumed.game()).unwrap(),
+      after_turn_one
+    );
+    assert_eq!(resumed.total_nodes(), 1);
+  }
+
+  #[test]
+  fn new_token_gets_a_fresh_id() {
+    let mut g = game_with_library(&["Forest"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(create_token("Soldier".to_string(), 1));
+    interpreter.apply(add_counter(CardId(2), "+1/+1", 3));
+    interpreter.apply(sacrifice(CardId(2))).unwrap();
+    assert!(interpreter.game().counters.is_empty());
+
+    // The sacrificed token had the highest ID, but it isn't given out again:
+    interpreter.apply(create_token("Soldier".to_string(), 1));
+    assert_eq!(interpreter.game().battlefield, vec![CardId(3)]);
+    assert!(interpreter.game().counters.is_empty());
+
+    // The ID is saved with the game, so a loaded game doesn't reuse it either:
+    interpreter.apply(sacrifice(CardId(3))).unwrap();
+    let saved = serde_json::to_value(interpreter.game()).unwrap();
+    let mut g: Game = serde_json::from_value(saved).unwrap();
+    assert_eq!(g.next_card_id(), CardId(4));
+  }
+
+  #[test]
+  fn bounced_permanent_loses_its_counters() {
+    let mut g = game_with_library(&["Grizzly Bears"]);
+    let mut interpreter = Interpreter::new(&mut g);
+
+    interpreter.apply(draw_card).unwrap();
+    interpreter.apply(play_permanent(CardId(1))).unwrap();
+    interpreter.apply(add_counter(CardId(1), "+1/+1", 2));
+
+    interpreter.apply(bounce(CardId(1))).unwrap();
+    assert!(interpreter.game().counters.is_empty());
+
+    // Played again, it's a new object with no counters:
+    interpreter.apply(play_permanent(CardId(1))).unwrap();
+    assert_eq!(interpreter.game().battlefield, vec![CardId(1)]);
+    assert!(interpreter.game().counters.is_empty());
+
+    // The same goes for cards moved together:
+    interpreter.apply(add_counter(CardId(1), "charge", 1));
+    interpreter
+      .apply(move_cards(vec![CardId(1)], Zone::Battlefield, Zone::Exile))
+      .unwrap();
+    assert!(interpreter.game().counters.is_empty());
+  }
+
+  #[test]
+  fn abandoned_step_leaves_the_game_alone() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    let before = g.position_hash();
+    let mut stepper = StepInterpreter::new(&mut g, |int| {
+      draw_card(int)?;
+      scry(1)(int)
+    });
+
+    assert!(matches!(stepper.step(), Step::NeedChoice(_)));
+    drop(stepper);
+    assert_eq!(g.position_hash(), before);
+    assert!(g.players[0].hand.is_empty());
+  }
+
+  #[test]
+  #[should_panic(expected = "No scripted choice to answer: Scry Swamp")]
+  fn scripted_interface_runs_out_of_choices() {
+    let mut g = game_with_library(&["Forest", "Island", "Swamp"]);
+    let mut interpreter = Interpreter::new(&mut g).with_interface(ScriptedInterface::new([]));
+    let _ = interpreter.apply(scry(2));
+  }
 }
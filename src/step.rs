@@ -0,0 +1,116 @@
+use std::collections::VecDeque;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{
+  effect_value::EffectTree,
+  interpreter::{ChoiceRequest, GameInterface, Interpreter},
+  Game,
+};
+
+/// Makes the choices provided so far in order, then leaves the next one pending
+/// for the caller to provide.
+struct StepInterface {
+  choices: VecDeque<usize>,
+}
+
+impl GameInterface for StepInterface {
+  fn choose(&mut self, request: &ChoiceRequest) -> usize {
+    panic!("No choice provided to answer: {}", request.prompt)
+  }
+
+  fn try_choose(&mut self, _: &ChoiceRequest) -> Option<usize> {
+    self.choices.pop_front()
+  }
+}
+
+/// Where a `StepInterpreter` stopped.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step<T> {
+  /// The effect needs a choice made, see `StepInterpreter::provide_choice`.
+  NeedChoice(ChoiceRequest),
+  /// The effect finished with this value.
+  Done(T),
+}
+
+/// An interpreter which runs an effect until it needs a choice, then stops and
+/// hands the choice back to the caller, rather than calling back into the user
+/// interface. This suits a game played over the network, where the answer
+/// arrives some time later.
+///
+/// An effect can't be suspended part way through, so each step runs it again
+/// from the start, against a fresh copy of the game, with the choices made so
+/// far. An effect with `n` choices therefore runs `n + 1` times, doing
+/// quadratic work overall, which is fine for the handful of choices one effect
+/// asks for. The effect must be deterministic for this to work, just as for
+/// replays, so it takes a `Fn` rather than a `FnOnce`. The choices are recorded
+/// in the effect tree like any other.
+///
+/// The game is only written back once the effect is done. If the stepper is
+/// dropped while a choice is pending, the game is as it was before.
+pub struct StepInterpreter<'a, F> {
+  game: &'a mut Game,
+  start: Game,
+  effect: F,
+  rng_seed: u64,
+  choices: Vec<usize>,
+  effects: Vec<EffectTree>,
+}
+
+impl<'a, T, F> StepInterpreter<'a, F>
+where
+  F: for<'x> Fn(&mut Interpreter<'x>) -> T,
+  T: Serialize + DeserializeOwned + 'static,
+{
+  /// A step interpreter which will apply `effect` to `game`.
+  pub fn new(game: &'a mut Game, effect: F) -> StepInterpreter<'a, F> {
+    StepInterpreter {
+      start: game.clone(),
+      game,
+      effect,
+      rng_seed: 0,
+      choices: Vec::new(),
+      effects: Vec::new(),
+    }
+  }
+
+  /// Seed the RNG, see `Interpreter::with_rng_seed`.
+  pub fn with_rng_seed(mut self, rng_seed: u64) -> Self {
+    self.rng_seed = rng_seed;
+    self
+  }
+
+  /// Run the effect until it needs a choice which hasn't been provided, or until
+  /// it's done, when the game is updated.
+  pub fn step(&mut self) -> Step<T> {
+    let mut game = self.start.clone();
+    let mut interpreter = Interpreter::new(&mut game)
+      .with_rng_seed(self.rng_seed)
+      .with_interface(StepInterface {
+        choices: self.choices.iter().copied().collect(),
+      });
+    let value = interpreter.apply(|int| (self.effect)(int));
+    let pending = interpreter.pending_choice();
+    self.effects = interpreter.into_effects();
+
+    match pending {
+      Some(request) => Step::NeedChoice(request),
+      None => {
+        *self.game = game;
+        Step::Done(value)
+      }
+    }
+  }
+
+  /// Answer the choice the effect stopped at with the index of the chosen
+  /// option, and carry on.
+  pub fn provide_choice(&mut self, index: usize) -> Step<T> {
+    self.choices.push(index);
+    self.step()
+  }
+
+  /// The effects applied by the latest step.
+  pub fn into_effects(self) -> Vec<EffectTree> {
+    self.effects
+  }
+}
@@ -0,0 +1,67 @@
+//! Nested effects write straight into their parent's node, rather than each
+//! building an interpreter of their own and splicing its effects back in. This
+//! counts allocations to check that nesting deeper only costs a bounded number
+//! of allocations per level. It lives in its own test binary, so that the
+//! counting allocator doesn't replace the allocator for every other test.
+
+use std::{
+  alloc::{GlobalAlloc, Layout, System},
+  cell::Cell,
+};
+
+use sandbox::{GameBuilder, Interpreter};
+
+struct CountingAllocator;
+
+thread_local! {
+  static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+    System.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Apply an effect nested `depth` levels deep.
+fn nest(int: &mut Interpreter<'_>, depth: usize) {
+  if depth > 0 {
+    int.apply(move |int| nest(int, depth - 1));
+  }
+}
+
+/// The allocations made applying a single top level effect nested `depth` deep,
+/// to a fresh interpreter.
+fn allocations_nesting(depth: usize) -> usize {
+  let mut game = GameBuilder::new().build();
+  let mut interpreter = Interpreter::new(&mut game);
+
+  let before = ALLOCATIONS.get();
+  nest(&mut interpreter, depth);
+  ALLOCATIONS.get() - before
+}
+
+#[test]
+fn nested_apply_allocates_a_bounded_amount_per_level() {
+  const LEVELS: usize = 16;
+
+  // Whatever the top level effect costs, e.g.: checkpointing the game, is the
+  // same for both, so the difference is what the extra levels cost. Each level
+  // pushes a node into its parent's children, and may encode its result, but
+  // nothing more.
+  let shallow = allocations_nesting(1);
+  let deep = allocations_nesting(1 + LEVELS);
+  assert!(
+    deep - shallow <= 3 * LEVELS,
+    "{LEVELS} extra levels made {} allocations",
+    deep - shallow
+  );
+}